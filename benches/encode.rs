@@ -0,0 +1,61 @@
+// Benches drive argon2 directly so each cost is measured without the crate's own
+// overhead, the settings mirror what PasswordCypherBuilder produces. Run with `cargo bench`.
+use argon2::{Algorithm, Argon2, Params, Version};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const MEMORY_COSTS: [u32; 3] = [19 * 1024, 46 * 1024, 64 * 1024];
+const TIME_COSTS: [u32; 3] = [1, 2, 3];
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    group.sample_size(10);
+    for m_cost in MEMORY_COSTS {
+        for t_cost in TIME_COSTS {
+            let params = Params::new(m_cost, t_cost, 1, None).expect("valid params");
+            let argon = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+            group.bench_with_input(
+                BenchmarkId::new(format!("m={m_cost}"), format!("t={t_cost}")),
+                &argon,
+                |b, argon| {
+                    let mut hash = [0u8; 32];
+                    b.iter(|| {
+                        argon
+                            .hash_password_into(
+                                b"correct horse battery staple",
+                                b"bench-salt-bytes",
+                                &mut hash,
+                            )
+                            .expect("hashing succeeds")
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+// What PasswordCypher saves by building its Argon2 once rather than on every verify
+fn verify_setup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("verify_setup");
+    let params = Params::new(1024, 1, 1, None).expect("valid params");
+    let shared = Argon2::new(Algorithm::Argon2id, Version::V0x13, params.clone());
+    let mut hash = [0u8; 32];
+    group.bench_function("shared", |b| {
+        b.iter(|| {
+            shared
+                .hash_password_into(b"123", b"bench-salt-bytes", &mut hash)
+                .expect("hashing succeeds")
+        })
+    });
+    group.bench_function("per_call", |b| {
+        b.iter(|| {
+            Argon2::new(Algorithm::Argon2id, Version::V0x13, params.clone())
+                .hash_password_into(b"123", b"bench-salt-bytes", &mut hash)
+                .expect("hashing succeeds")
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, encode, verify_setup);
+criterion_main!(benches);
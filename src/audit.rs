@@ -0,0 +1,133 @@
+use std::{
+    fmt,
+    sync::{Mutex, PoisonError},
+    time::SystemTime,
+};
+
+use crate::to_unix_seconds;
+
+/// Something security relevant happened to an account. Events only ever carry who and
+/// when, never a password or hash, so sinks can ship them anywhere.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditEvent {
+    LoginSuccess {
+        user_id: i64,
+        username: String,
+        at: SystemTime,
+    },
+    // The username is as typed, it may not belong to any account
+    LoginFailure {
+        username: String,
+        at: SystemTime,
+    },
+    AccountLocked {
+        username: String,
+        at: SystemTime,
+    },
+    PasswordChanged {
+        user_id: i64,
+        at: SystemTime,
+    },
+}
+
+impl AuditEvent {
+    pub fn at(&self) -> SystemTime {
+        match self {
+            Self::LoginSuccess { at, .. }
+            | Self::LoginFailure { at, .. }
+            | Self::AccountLocked { at, .. }
+            | Self::PasswordChanged { at, .. } => *at,
+        }
+    }
+}
+
+impl fmt::Display for AuditEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let at = to_unix_seconds(self.at());
+        match self {
+            Self::LoginSuccess {
+                user_id, username, ..
+            } => write!(
+                f,
+                "{at} login success user_id={user_id} username={username}"
+            ),
+            Self::LoginFailure { username, .. } => {
+                write!(f, "{at} login failure username={username}")
+            }
+            Self::AccountLocked { username, .. } => {
+                write!(f, "{at} account locked username={username}")
+            }
+            Self::PasswordChanged { user_id, .. } => {
+                write!(f, "{at} password changed user_id={user_id}")
+            }
+        }
+    }
+}
+
+/// Where audit events go, e.g. a log pipeline or a database table
+pub trait AuditSink {
+    fn record(&self, event: AuditEvent);
+}
+
+/// Keeps events in memory, handy for tests and admin views of recent activity
+#[derive(Debug, Default)]
+pub struct InMemoryAuditSink {
+    events: Mutex<Vec<AuditEvent>>,
+}
+
+impl InMemoryAuditSink {
+    pub fn events(&self) -> Vec<AuditEvent> {
+        self.events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl AuditSink for InMemoryAuditSink {
+    fn record(&self, event: AuditEvent) {
+        self.events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(event);
+    }
+}
+
+/// Prints one line per event, for when stdout is collected as the log
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdoutAuditSink;
+
+impl AuditSink for StdoutAuditSink {
+    fn record(&self, event: AuditEvent) {
+        println!("audit: {event}");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_audit_event_display() {
+        let event = AuditEvent::LoginSuccess {
+            user_id: 1,
+            username: "someuser100".into(),
+            at: crate::from_unix_seconds(1700000000),
+        };
+        assert_eq!(
+            event.to_string(),
+            "1700000000 login success user_id=1 username=someuser100"
+        );
+    }
+
+    #[test]
+    fn test_in_memory_audit_sink() {
+        let sink = InMemoryAuditSink::default();
+        let event = AuditEvent::PasswordChanged {
+            user_id: 1,
+            at: SystemTime::now(),
+        };
+        sink.record(event.clone());
+        assert_eq!(sink.events(), vec![event]);
+    }
+}
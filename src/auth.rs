@@ -0,0 +1,595 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    time::{Duration, Instant, SystemTime},
+};
+
+use derive_getters::Getters;
+
+use crate::{
+    audit::{AuditEvent, AuditSink},
+    repository::UserRepository,
+    verify_legacy, ApplicationError, Password, PasswordCypher, Result, SecretString, User,
+};
+
+const DEFAULT_MAX_FAILURES: u32 = 5;
+const DEFAULT_COOLDOWN: Duration = Duration::from_secs(15 * 60);
+#[cfg(feature = "tokio")]
+const DEFAULT_TARPIT_BASE_DELAY: Duration = Duration::from_millis(250);
+#[cfg(feature = "tokio")]
+const DEFAULT_TARPIT_MAX_DELAY: Duration = Duration::from_secs(8);
+
+/// A successful login
+#[derive(Debug, Clone, Getters)]
+pub struct Authenticated {
+    user: User,
+    // Set when the stored password was out of date, e.g. an older CredentialVersion or a
+    // legacy bcrypt or scrypt hash, and was re-encoded with the cypher's current settings.
+    // The user already carries the new password, save it with `UserRepository::update`.
+    upgraded_password: Option<Password>,
+}
+
+impl Authenticated {
+    pub fn into_user(self) -> User {
+        self.user
+    }
+}
+
+/// Looks up the user and checks their password, returning the user on success.
+///
+/// A wrong password and an unknown username both return `Ok(None)`, and an unknown
+/// username still costs a hash via `dummy_verify`, so callers can't tell which one failed.
+/// Both outcomes are recorded in `audit`. The right password for an account that
+/// isn't active is refused with the error for its status.
+///
+/// Passwords stored with outdated settings, or imported bcrypt and scrypt hashes, are
+/// verified as they are and then re-encoded, see `Authenticated::upgraded_password`.
+pub fn authenticate(
+    repo: &impl UserRepository,
+    cypher: &PasswordCypher,
+    audit: &dyn AuditSink,
+    username: &str,
+    raw_password: impl Into<SecretString>,
+) -> Result<Option<Authenticated>> {
+    let raw_password = raw_password.into();
+    let user = authenticate_constant_time(repo, cypher, username, raw_password.clone())?;
+    // Only reveal the account's state to someone who knows its password
+    if let Some(Err(e)) = user.as_ref().map(|user| user.status.ensure_active()) {
+        audit.record(AuditEvent::LoginFailure {
+            username: username.into(),
+            at: SystemTime::now(),
+        });
+        return Err(e);
+    }
+    audit.record(match &user {
+        Some(user) => AuditEvent::LoginSuccess {
+            user_id: user.id,
+            username: user.username.clone(),
+            at: SystemTime::now(),
+        },
+        None => AuditEvent::LoginFailure {
+            username: username.into(),
+            at: SystemTime::now(),
+        },
+    });
+    let Some(mut user) = user else {
+        return Ok(None);
+    };
+    let upgraded_password = if user.legacy_password.is_some() || cypher.needs_rehash(&user.password)
+    {
+        let password = cypher.encode(raw_password)?;
+        user.password = password.clone();
+        user.legacy_password = None;
+        Some(password)
+    } else {
+        None
+    };
+    Ok(Some(Authenticated {
+        user,
+        upgraded_password,
+    }))
+}
+
+/// Looks up the user and checks their password, the user is only returned if it matches.
+///
+/// Both the lookup and one hash always happen, an unknown username is hashed with
+/// `dummy_verify`, so the time taken doesn't tell whether the account exists. The cost is
+/// a full hash for every miss, which also makes guessing usernames as slow as passwords.
+/// It doesn't audit or check the account's status, `authenticate` builds on it for that.
+pub fn authenticate_constant_time(
+    repo: &impl UserRepository,
+    cypher: &PasswordCypher,
+    username: &str,
+    raw_password: impl Into<SecretString>,
+) -> Result<Option<User>> {
+    let raw_password = raw_password.into();
+    match repo.find_by_username(username)? {
+        Some(user) => {
+            let verified = match &user.legacy_password {
+                Some(stored) => verify_legacy(stored, raw_password)?,
+                None => user.verify_password(cypher, raw_password)?,
+            };
+            Ok(verified.then_some(user))
+        }
+        None => {
+            cypher.dummy_verify(raw_password)?;
+            Ok(None)
+        }
+    }
+}
+
+/// Same as `authenticate`, but refuses locked keys and records the outcome in `throttle`.
+/// A refused attempt is still audited as a login failure.
+pub fn authenticate_throttled(
+    repo: &impl UserRepository,
+    cypher: &PasswordCypher,
+    audit: &dyn AuditSink,
+    throttle: &mut LoginThrottle,
+    username: &str,
+    raw_password: impl Into<SecretString>,
+) -> Result<Option<Authenticated>> {
+    if throttle.is_locked(username) {
+        audit.record(AuditEvent::LoginFailure {
+            username: username.into(),
+            at: SystemTime::now(),
+        });
+        return Err(ApplicationError::AccountLocked);
+    }
+    let user = authenticate(repo, cypher, audit, username, raw_password)?;
+    match user {
+        Some(_) => throttle.record_success(username),
+        None => {
+            throttle.record_failure(username);
+            if throttle.is_locked(username) {
+                audit.record(AuditEvent::AccountLocked {
+                    username: username.into(),
+                    at: SystemTime::now(),
+                });
+            }
+        }
+    }
+    Ok(user)
+}
+
+/// Same as `authenticate_throttled`, but also returns how long to wait before answering,
+/// the tarpit's delay for the key's consecutive failures, zero on success. This slows
+/// guessing without a lockout.
+///
+/// The throttle is only borrowed while checking, so a throttle shared behind a lock can be
+/// released before awaiting `Tarpit::sleep`. Otherwise one attacker's delay would hold up
+/// every other login.
+pub fn authenticate_tarpitted<S: Sleeper>(
+    repo: &impl UserRepository,
+    cypher: &PasswordCypher,
+    audit: &dyn AuditSink,
+    throttle: &mut LoginThrottle,
+    tarpit: &Tarpit<S>,
+    username: &str,
+    raw_password: impl Into<SecretString>,
+) -> Result<(Option<Authenticated>, Duration)> {
+    let authenticated =
+        authenticate_throttled(repo, cypher, audit, throttle, username, raw_password)?;
+    let delay = match authenticated {
+        Some(_) => Duration::ZERO,
+        None => tarpit.delay_for(throttle.failures(username)),
+    };
+    Ok((authenticated, delay))
+}
+
+/// Waits out a tarpit delay without blocking the thread, swap it out to keep tests fast
+pub trait Sleeper {
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + Send;
+}
+
+/// Sleeps on the tokio timer
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioSleeper;
+
+#[cfg(feature = "tokio")]
+impl Sleeper for TokioSleeper {
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + Send {
+        tokio::time::sleep(duration)
+    }
+}
+
+/// A delay doubling with each consecutive failure, from `base_delay` up to `max_delay`
+#[derive(Debug, Clone)]
+pub struct Tarpit<S> {
+    base_delay: Duration,
+    max_delay: Duration,
+    sleeper: S,
+}
+
+/// 250ms doubling up to 8s
+#[cfg(feature = "tokio")]
+impl Default for Tarpit<TokioSleeper> {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_TARPIT_BASE_DELAY,
+            DEFAULT_TARPIT_MAX_DELAY,
+            TokioSleeper,
+        )
+    }
+}
+
+impl<S: Sleeper> Tarpit<S> {
+    pub fn new(base_delay: Duration, max_delay: Duration, sleeper: S) -> Self {
+        Self {
+            base_delay,
+            max_delay,
+            sleeper,
+        }
+    }
+
+    pub fn with_sleeper<T: Sleeper>(self, sleeper: T) -> Tarpit<T> {
+        Tarpit {
+            base_delay: self.base_delay,
+            max_delay: self.max_delay,
+            sleeper,
+        }
+    }
+
+    /// Waits out a delay from `authenticate_tarpitted` without blocking the thread
+    pub fn sleep(&self, delay: Duration) -> impl Future<Output = ()> + Send + '_ {
+        self.sleeper.sleep(delay)
+    }
+
+    /// No delay before the first failure
+    pub fn delay_for(&self, failures: u32) -> Duration {
+        match failures {
+            0 => Duration::ZERO,
+            n => self
+                .base_delay
+                .saturating_mul(2u32.saturating_pow(n - 1))
+                .min(self.max_delay),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Attempts {
+    failures: u32,
+    locked_until: Option<Instant>,
+}
+
+/// Counts failed logins per key (a username or an IP) and locks the key after too many
+#[derive(Debug, Clone)]
+pub struct LoginThrottle {
+    max_failures: u32,
+    cooldown: Duration,
+    attempts: HashMap<String, Attempts>,
+}
+
+impl Default for LoginThrottle {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FAILURES, DEFAULT_COOLDOWN)
+    }
+}
+
+impl LoginThrottle {
+    pub fn new(max_failures: u32, cooldown: Duration) -> Self {
+        Self {
+            max_failures,
+            cooldown,
+            attempts: HashMap::new(),
+        }
+    }
+
+    /// Counts a failure, locking the key for the cooldown once it hits `max_failures`
+    pub fn record_failure(&mut self, key: &str) {
+        let now = Instant::now();
+        let attempts = self.attempts.entry(key.to_owned()).or_default();
+        if attempts.locked_until.is_some_and(|until| until <= now) {
+            *attempts = Attempts::default();
+        }
+        attempts.failures += 1;
+        if attempts.failures >= self.max_failures {
+            attempts.locked_until = Some(now + self.cooldown);
+        }
+    }
+
+    /// Clears the key's failure count after a good login
+    pub fn record_success(&mut self, key: &str) {
+        self.attempts.remove(key);
+    }
+
+    /// Failures since the key's last good login or expired lock
+    pub fn failures(&self, key: &str) -> u32 {
+        self.attempts
+            .get(key)
+            .map(|attempts| attempts.failures)
+            .unwrap_or_default()
+    }
+
+    pub fn is_locked(&self, key: &str) -> bool {
+        self.attempts
+            .get(key)
+            .and_then(|attempts| attempts.locked_until)
+            .is_some_and(|until| Instant::now() < until)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use argon2::{Algorithm, Params, Version};
+
+    use crate::{
+        audit::InMemoryAuditSink, repository::InMemoryUserRepository, AccountStatus,
+        CredentialVersion, UserBuilder,
+    };
+
+    fn repo_with_user(cypher: &PasswordCypher) -> Result<InMemoryUserRepository> {
+        let mut repo = InMemoryUserRepository::default();
+        repo.insert(
+            UserBuilder::default()
+                .id(1)
+                .username("someuser100".into())
+                .email("someuser100@protomail.com".into())
+                .password(cypher.encode("123")?)
+                .language("en".parse()?)
+                .status(AccountStatus::Active)
+                .build()?,
+        )?;
+        Ok(repo)
+    }
+
+    #[test]
+    fn test_authenticate() -> Result<()> {
+        let cypher = PasswordCypher::default();
+        let repo = repo_with_user(&cypher)?;
+        let audit = InMemoryAuditSink::default();
+        let authenticated = authenticate(&repo, &cypher, &audit, "someuser100", "123")?
+            .ok_or(ApplicationError::IncorrectPassword)?;
+        assert_eq!(authenticated.user().id(), &1);
+        assert!(authenticated.upgraded_password().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_authenticate_upgrades_old_credentials() -> Result<()> {
+        let params = Params::new(1024, 2, 1, None)?;
+        let v1 = PasswordCypher::new(Algorithm::Argon2i, Version::V0x10, params.clone());
+        let mut repo = repo_with_user(&v1)?;
+        let audit = InMemoryAuditSink::default();
+        let current = PasswordCypher::new(Algorithm::Argon2id, Version::V0x13, params);
+        let authenticated = authenticate(&repo, &current, &audit, "someuser100", "123")?
+            .ok_or(ApplicationError::IncorrectPassword)?;
+        let upgraded = authenticated
+            .upgraded_password()
+            .clone()
+            .ok_or(ApplicationError::PasswordHash("not upgraded".into()))?;
+        assert_eq!(upgraded.version(), CredentialVersion::V2);
+        assert!(current.try_password(&upgraded, "123")?);
+        assert!(!current.needs_rehash(&authenticated.user().password));
+        repo.update(authenticated.into_user())?;
+        let again = authenticate(&repo, &current, &audit, "someuser100", "123")?
+            .ok_or(ApplicationError::IncorrectPassword)?;
+        assert!(again.upgraded_password().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_authenticate_wrong_password() -> Result<()> {
+        let cypher = PasswordCypher::default();
+        let repo = repo_with_user(&cypher)?;
+        let audit = InMemoryAuditSink::default();
+        assert!(authenticate(&repo, &cypher, &audit, "someuser100", "1234")?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_authenticate_inactive() -> Result<()> {
+        let cypher = PasswordCypher::default();
+        let mut repo = repo_with_user(&cypher)?;
+        let audit = InMemoryAuditSink::default();
+        for (status, expected) in [
+            (AccountStatus::Disabled, ApplicationError::AccountDisabled),
+            (AccountStatus::Locked, ApplicationError::AccountLocked),
+            (
+                AccountStatus::PendingVerification,
+                ApplicationError::AccountPendingVerification,
+            ),
+        ] {
+            let mut user = repo.find_by_id(1)?.ok_or(ApplicationError::UserNotFound)?;
+            user.set_status(status);
+            repo.update(user)?;
+            let error = authenticate(&repo, &cypher, &audit, "someuser100", "123").unwrap_err();
+            assert_eq!(error.to_string(), expected.to_string());
+            // A wrong password doesn't reveal the status
+            assert!(authenticate(&repo, &cypher, &audit, "someuser100", "1234")?.is_none());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_authenticate_audits_failure() -> Result<()> {
+        let cypher = PasswordCypher::default();
+        let repo = repo_with_user(&cypher)?;
+        let audit = InMemoryAuditSink::default();
+        authenticate(&repo, &cypher, &audit, "someuser100", "hunter2")?;
+        let events = audit.events();
+        assert!(matches!(
+            events.as_slice(),
+            [AuditEvent::LoginFailure { username, .. }] if username == "someuser100"
+        ));
+        for logged in [format!("{:?}", events[0]), events[0].to_string()] {
+            assert!(!logged.contains("hunter2"));
+            assert!(!logged.contains("argon2"));
+        }
+        authenticate(&repo, &cypher, &audit, "someuser100", "123")?;
+        assert!(matches!(
+            audit.events().last(),
+            Some(AuditEvent::LoginSuccess { user_id: 1, .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_login_throttle_locks() {
+        let mut throttle = LoginThrottle::new(3, Duration::from_secs(60));
+        throttle.record_failure("someuser100");
+        throttle.record_failure("someuser100");
+        assert!(!throttle.is_locked("someuser100"));
+        throttle.record_failure("someuser100");
+        assert!(throttle.is_locked("someuser100"));
+        assert!(!throttle.is_locked("someuser200"));
+    }
+
+    #[test]
+    fn test_login_throttle_success_clears() {
+        let mut throttle = LoginThrottle::new(3, Duration::from_secs(60));
+        throttle.record_failure("someuser100");
+        throttle.record_failure("someuser100");
+        throttle.record_success("someuser100");
+        throttle.record_failure("someuser100");
+        throttle.record_failure("someuser100");
+        assert!(!throttle.is_locked("someuser100"));
+    }
+
+    #[test]
+    fn test_login_throttle_cooldown_expires() {
+        let mut throttle = LoginThrottle::new(1, Duration::ZERO);
+        throttle.record_failure("someuser100");
+        assert!(!throttle.is_locked("someuser100"));
+    }
+
+    #[test]
+    fn test_authenticate_throttled() -> Result<()> {
+        let cypher = PasswordCypher::default();
+        let repo = repo_with_user(&cypher)?;
+        let audit = InMemoryAuditSink::default();
+        let mut throttle = LoginThrottle::new(2, Duration::from_secs(60));
+        assert!(
+            authenticate_throttled(&repo, &cypher, &audit, &mut throttle, "someuser100", "1")?
+                .is_none()
+        );
+        assert!(authenticate_throttled(
+            &repo,
+            &cypher,
+            &audit,
+            &mut throttle,
+            "someuser100",
+            "123"
+        )?
+        .is_some());
+        authenticate_throttled(&repo, &cypher, &audit, &mut throttle, "someuser100", "1")?;
+        authenticate_throttled(&repo, &cypher, &audit, &mut throttle, "someuser100", "2")?;
+        assert!(matches!(
+            authenticate_throttled(&repo, &cypher, &audit, &mut throttle, "someuser100", "123"),
+            Err(ApplicationError::AccountLocked)
+        ));
+        assert!(matches!(
+            audit.events().as_slice(),
+            [
+                ..,
+                AuditEvent::AccountLocked { .. },
+                AuditEvent::LoginFailure { username, .. },
+            ] if username == "someuser100"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_authenticate_constant_time() -> Result<()> {
+        let cypher = PasswordCypher::default();
+        let repo = repo_with_user(&cypher)?;
+        assert!(authenticate_constant_time(&repo, &cypher, "someuser100", "1234")?.is_none());
+        assert!(authenticate_constant_time(&repo, &cypher, "someuser200", "123")?.is_none());
+        assert!(authenticate_constant_time(&repo, &cypher, "someuser100", "123")?.is_some());
+        Ok(())
+    }
+
+    #[derive(Default)]
+    struct MockSleeper {
+        slept: std::sync::Mutex<Vec<Duration>>,
+    }
+
+    impl Sleeper for &MockSleeper {
+        fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + Send {
+            self.slept.lock().unwrap().push(duration);
+            std::future::ready(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_tarpitted() -> Result<()> {
+        let cypher = PasswordCypher::default();
+        let repo = repo_with_user(&cypher)?;
+        let audit = InMemoryAuditSink::default();
+        let mut throttle = LoginThrottle::new(10, Duration::from_secs(60));
+        let sleeper = MockSleeper::default();
+        let tarpit = Tarpit::new(
+            Duration::from_millis(100),
+            Duration::from_millis(300),
+            &sleeper,
+        );
+        for raw in ["1", "2", "3", "4", "123", "1"] {
+            let (_, delay) = authenticate_tarpitted(
+                &repo,
+                &cypher,
+                &audit,
+                &mut throttle,
+                &tarpit,
+                "someuser100",
+                raw,
+            )?;
+            tarpit.sleep(delay).await;
+        }
+        assert_eq!(
+            *sleeper.slept.lock().unwrap(),
+            [100, 200, 300, 300, 0, 100].map(Duration::from_millis)
+        );
+        assert_eq!(tarpit.delay_for(0), Duration::ZERO);
+        assert_eq!(tarpit.delay_for(u32::MAX), Duration::from_millis(300));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_tarpitted_releases_throttle_before_sleeping() -> Result<()> {
+        let cypher = PasswordCypher::default();
+        let repo = repo_with_user(&cypher)?;
+        let audit = InMemoryAuditSink::default();
+        let throttle = std::sync::Mutex::new(LoginThrottle::default());
+        let sleeper = MockSleeper::default();
+        let tarpit = Tarpit::new(Duration::from_secs(1), Duration::from_secs(8), &sleeper);
+        let (authenticated, delay) = authenticate_tarpitted(
+            &repo,
+            &cypher,
+            &audit,
+            &mut throttle.lock().unwrap(),
+            &tarpit,
+            "someuser100",
+            "1",
+        )?;
+        assert!(authenticated.is_none());
+        let sleep = tarpit.sleep(delay);
+        // Another login can take the throttle while this one is still waiting
+        assert_eq!(
+            throttle.try_lock().map(|t| t.failures("someuser100")).ok(),
+            Some(1)
+        );
+        sleep.await;
+        assert_eq!(*sleeper.slept.lock().unwrap(), [Duration::from_secs(1)]);
+        Ok(())
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_tarpit_default_sleeps_on_tokio() {
+        let tarpit = Tarpit::default();
+        assert_eq!(tarpit.delay_for(1), Duration::from_millis(250));
+        assert_eq!(tarpit.delay_for(10), Duration::from_secs(8));
+        tarpit.sleep(Duration::from_millis(1)).await;
+    }
+
+    #[test]
+    fn test_authenticate_unknown_user() -> Result<()> {
+        let cypher = PasswordCypher::default();
+        let repo = repo_with_user(&cypher)?;
+        let audit = InMemoryAuditSink::default();
+        assert!(authenticate(&repo, &cypher, &audit, "someuser200", "123")?.is_none());
+        Ok(())
+    }
+}
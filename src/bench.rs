@@ -0,0 +1,68 @@
+use std::{fmt, time::Instant};
+
+use derive_getters::Getters;
+
+use crate::{PasswordCypherBuilder, Result};
+
+/// How long one Argon2 configuration took to hash a password
+#[derive(Debug, Clone, Getters)]
+pub struct BenchResult {
+    memory_cost: u32,
+    time_cost: u32,
+    millis_per_hash: f64,
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "m={} KiB, t={}: {:.2} ms/hash",
+            self.memory_cost, self.time_cost, self.millis_per_hash
+        )
+    }
+}
+
+/// Times `encode` for every memory and time cost combination, averaging over
+/// `iterations` hashes each, so parameters can be picked for the hardware it runs on.
+/// Run it with a release build, debug numbers are meaningless.
+pub fn run_matrix(
+    memory_costs: &[u32],
+    time_costs: &[u32],
+    iterations: u32,
+) -> Result<Vec<BenchResult>> {
+    let iterations = iterations.max(1);
+    let mut results = Vec::with_capacity(memory_costs.len() * time_costs.len());
+    for &memory_cost in memory_costs {
+        for &time_cost in time_costs {
+            let cypher = PasswordCypherBuilder::default()
+                .memory_cost(memory_cost)
+                .time_cost(time_cost)
+                .build()?;
+            let start = Instant::now();
+            for _ in 0..iterations {
+                cypher.encode("correct horse battery staple")?;
+            }
+            results.push(BenchResult {
+                memory_cost,
+                time_cost,
+                millis_per_hash: start.elapsed().as_secs_f64() * 1000.0 / f64::from(iterations),
+            });
+        }
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bench_smoke() -> Result<()> {
+        let results = run_matrix(&[1024, 2048], &[1], 1)?;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].memory_cost(), &2048);
+        assert!(results.iter().all(|r| *r.millis_per_hash() > 0.0));
+        assert!(results[0].to_string().starts_with("m=1024 KiB, t=1: "));
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+use sha1::{Digest, Sha1};
+
+use crate::{normalize, ApplicationError, Result, SecretString};
+
+const RANGE_API: &str = "https://api.pwnedpasswords.com/range/";
+
+/// How many times the password shows up in HaveIBeenPwned's breach corpus, 0 if never
+///
+/// Only the first five hex characters of the SHA-1 leave the machine (k-anonymity), the
+/// suffix is matched locally against the returned range.
+pub fn breach_check(raw_password: impl Into<SecretString>) -> Result<u64> {
+    breach_check_with(raw_password, fetch_range)
+}
+
+fn fetch_range(prefix: &str) -> Result<String> {
+    ureq::get(&format!("{RANGE_API}{prefix}"))
+        // Padding hides the real range size from anyone watching the response length
+        .set("Add-Padding", "true")
+        .call()
+        .map_err(|e| ApplicationError::BreachCheck(e.to_string()))?
+        .into_string()
+        .map_err(|e| ApplicationError::BreachCheck(e.to_string()))
+}
+
+fn breach_check_with(
+    raw_password: impl Into<SecretString>,
+    fetch: impl FnOnce(&str) -> Result<String>,
+) -> Result<u64> {
+    let raw_password = raw_password.into();
+    let digest = Sha1::digest(normalize(raw_password.expose_secret()).as_bytes());
+    let hex: String = digest.iter().map(|b| format!("{b:02X}")).collect();
+    let (prefix, suffix) = hex.split_at(5);
+    let body = fetch(prefix)?;
+    // Each line is "SUFFIX:COUNT", padding entries have a count of 0
+    for line in body.lines() {
+        let Some((candidate, count)) = line.trim().split_once(':') else {
+            continue;
+        };
+        if candidate.eq_ignore_ascii_case(suffix) {
+            return count
+                .parse()
+                .map_err(|_| ApplicationError::BreachCheck(format!("bad count '{count}'")));
+        }
+    }
+    Ok(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // SHA-1 of "password" is 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8
+    const RANGE: &str = "1E2AAA439972480CEC7F16C795BBB429372:1\r\n\
+                         1E4C9B93F3F0682250B6CF8331B7EE68FD8:10434004\r\n\
+                         1E4D6D8B3BEF1E5FE1E0FB5E8C4A7D2D4A3:0";
+
+    #[test]
+    fn test_breach_check_pwned() -> Result<()> {
+        let count = breach_check_with("password", |prefix| {
+            assert_eq!(prefix, "5BAA6");
+            Ok(RANGE.into())
+        })?;
+        assert_eq!(count, 10434004);
+        Ok(())
+    }
+
+    #[test]
+    fn test_breach_check_not_found() -> Result<()> {
+        assert_eq!(breach_check_with("correct horse", |_| Ok(RANGE.into()))?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_breach_check_bad_response() {
+        let bad = "1E4C9B93F3F0682250B6CF8331B7EE68FD8:lots";
+        assert!(matches!(
+            breach_check_with("password", |_| Ok(bad.into())),
+            Err(ApplicationError::BreachCheck(_))
+        ));
+    }
+}
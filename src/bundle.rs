@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{AccountStatus, ApplicationError, Language, Password, Result, User, UserBuilder};
+
+/// Bumped whenever the bundle's fields change
+pub const CREDENTIAL_BUNDLE_VERSION: u32 = 1;
+
+// What's needed to recreate a user on another instance, the password stays a PHC string
+#[derive(Serialize, Deserialize)]
+struct CredentialBundle {
+    version: u32,
+    id: i64,
+    username: String,
+    email: String,
+    language: Language,
+    password: Password,
+    status: AccountStatus,
+}
+
+// Read on its own first, so a newer bundle is refused by version rather than by shape
+#[derive(Deserialize)]
+struct BundleVersion {
+    version: u32,
+}
+
+impl User {
+    /// A versioned JSON document for backing up a user or moving it between instances
+    pub fn to_credential_bundle(&self) -> String {
+        let bundle = CredentialBundle {
+            version: CREDENTIAL_BUNDLE_VERSION,
+            id: self.id,
+            username: self.username.clone(),
+            email: self.email.clone(),
+            language: self.language.clone(),
+            password: self.password.clone(),
+            status: self.status,
+        };
+        serde_json::to_string(&bundle).expect("bundles only hold strings and numbers")
+    }
+
+    /// Restores a user from `to_credential_bundle`, the fields are validated like any
+    /// other new user's
+    pub fn from_credential_bundle(s: &str) -> Result<User> {
+        let encoding = |e: serde_json::Error| ApplicationError::Encoding(e.to_string());
+        let BundleVersion { version } = serde_json::from_str(s).map_err(encoding)?;
+        if version != CREDENTIAL_BUNDLE_VERSION {
+            return Err(ApplicationError::UnsupportedBundleVersion(version));
+        }
+        let bundle: CredentialBundle = serde_json::from_str(s).map_err(encoding)?;
+        UserBuilder::default()
+            .id(bundle.id)
+            .username(bundle.username)
+            .email(bundle.email)
+            .language(bundle.language)
+            .password(bundle.password)
+            .status(bundle.status)
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::PasswordCypherBuilder;
+
+    #[test]
+    fn test_credential_bundle_round_trip() -> Result<()> {
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let user = UserBuilder::default()
+            .id(1)
+            .username("someuser100".into())
+            .email("someuser100@protomail.com".into())
+            .raw_password(&cypher, "123")?
+            .language(Language::French)
+            .status(AccountStatus::Active)
+            .build()?;
+        let bundle = user.to_credential_bundle();
+        assert!(bundle.starts_with("{\"version\":1,"));
+        let restored = User::from_credential_bundle(&bundle)?;
+        assert_eq!(restored, user);
+        assert_eq!(restored.username(), "someuser100");
+        assert_eq!(restored.email(), "someuser100@protomail.com");
+        assert_eq!(restored.language(), &Language::French);
+        assert_eq!(restored.status(), &AccountStatus::Active);
+        assert!(restored.verify_password(&cypher, "123")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_credential_bundle_rejects_unknown_version() {
+        assert!(matches!(
+            User::from_credential_bundle("{\"version\":2,\"username\":\"someuser100\"}"),
+            Err(ApplicationError::UnsupportedBundleVersion(2))
+        ));
+        assert!(matches!(
+            User::from_credential_bundle("{\"username\":\"someuser100\"}"),
+            Err(ApplicationError::Encoding(_))
+        ));
+    }
+}
@@ -0,0 +1,97 @@
+use std::{
+    collections::HashSet,
+    fmt,
+    io::{self, BufRead},
+};
+
+use crate::normalize;
+
+/// Passwords that are refused outright, e.g. a top 10k list of leaked passwords.
+/// Matching ignores case, so "Password" is as guessable as "password".
+#[derive(Clone, Default)]
+pub struct Denylist {
+    entries: HashSet<String>,
+}
+
+impl fmt::Debug for Denylist {
+    // A full list is thousands of lines, it's not worth printing
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Denylist")
+            .field("len", &self.entries.len())
+            .finish()
+    }
+}
+
+impl Denylist {
+    /// One password per line, blank lines are skipped
+    pub fn new(list: &str) -> Self {
+        list.lines().collect()
+    }
+
+    /// Reads a newline delimited list line by line, e.g. from a file
+    pub fn from_reader(reader: impl BufRead) -> io::Result<Self> {
+        let mut denylist = Self::default();
+        for line in reader.lines() {
+            denylist.insert(&line?);
+        }
+        Ok(denylist)
+    }
+
+    pub fn insert(&mut self, password: &str) {
+        let password = password.trim_end_matches('\r');
+        if !password.is_empty() {
+            self.entries.insert(canonical(password));
+        }
+    }
+
+    pub fn contains(&self, raw_password: &str) -> bool {
+        self.entries.contains(&canonical(raw_password))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<'a> FromIterator<&'a str> for Denylist {
+    fn from_iter<I: IntoIterator<Item = &'a str>>(iter: I) -> Self {
+        let mut denylist = Self::default();
+        for password in iter {
+            denylist.insert(password);
+        }
+        denylist
+    }
+}
+
+// Normalized the same way encode does, so lookalike forms of a listed password match too
+fn canonical(password: &str) -> String {
+    normalize(password).to_lowercase()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_denylist() {
+        let denylist = Denylist::new("123456\npassword\r\n\nqwerty\n");
+        assert_eq!(denylist.len(), 3);
+        assert!(denylist.contains("password"));
+        assert!(denylist.contains("PassWord"));
+        assert!(!denylist.contains("correct horse"));
+        assert!(!denylist.contains(""));
+    }
+
+    #[test]
+    fn test_denylist_from_reader() -> io::Result<()> {
+        let denylist = Denylist::from_reader(io::Cursor::new("letmein\ndragon\n"))?;
+        assert!(denylist.contains("Dragon"));
+        assert!(!denylist.contains("dragonfly"));
+        assert_eq!(format!("{denylist:?}"), "Denylist { len: 2 }");
+        Ok(())
+    }
+}
@@ -0,0 +1,38 @@
+use base64ct::{Base64UrlUnpadded, Encoding};
+
+use crate::{ApplicationError, Result};
+
+/// URL-safe base64 without padding, for tokens that end up in links, cookies and forms
+pub(crate) fn encode_urlsafe_nopad(bytes: &[u8]) -> String {
+    Base64UrlUnpadded::encode_string(bytes)
+}
+
+pub(crate) fn decode_urlsafe_nopad(encoded: &str) -> Result<Vec<u8>> {
+    Base64UrlUnpadded::decode_vec(encoded).map_err(|e| ApplicationError::Encoding(e.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_urlsafe_nopad_round_trip() -> Result<()> {
+        for bytes in [&[][..], &[0], &[0xff, 0xfe], &[0xfb, 0xff, 0xbf], &[7; 33]] {
+            let encoded = encode_urlsafe_nopad(bytes);
+            assert!(!encoded.contains(['=', '+', '/']));
+            assert_eq!(decode_urlsafe_nopad(&encoded)?, bytes);
+        }
+        assert_eq!(encode_urlsafe_nopad(&[0xfb, 0xff, 0xbf]), "-_-_");
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_urlsafe_nopad_rejects_malformed() {
+        for malformed in ["A", "ab+/", "YQ==", "not base64!"] {
+            assert!(matches!(
+                decode_urlsafe_nopad(malformed),
+                Err(ApplicationError::Encoding(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::VecDeque;
+
+use crate::{Password, PasswordCypher, Result, SecretString};
+
+/// How many previous passwords a user can't go back to, by default
+pub const DEFAULT_PASSWORD_HISTORY: usize = 5;
+
+/// A user's most recent previous password hashes, oldest are dropped once it's full
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PasswordHistory {
+    capacity: usize,
+    hashes: VecDeque<Password>,
+}
+
+impl Default for PasswordHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_PASSWORD_HISTORY)
+    }
+}
+
+impl PasswordHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            hashes: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn push(&mut self, password: Password) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.hashes.len() >= self.capacity {
+            self.hashes.pop_front();
+        }
+        self.hashes.push_back(password);
+    }
+
+    /// Every hash has its own salt, so the candidate is hashed once per entry
+    pub fn was_recently_used(
+        &self,
+        cypher: &PasswordCypher,
+        raw_password: impl Into<SecretString>,
+    ) -> Result<bool> {
+        let raw_password = raw_password.into();
+        for password in &self.hashes {
+            if cypher.try_password(password, raw_password.clone())? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Password> {
+        self.hashes.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::PasswordCypherBuilder;
+
+    #[test]
+    fn test_password_history() -> Result<()> {
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let mut history = PasswordHistory::new(2);
+        for raw in ["123", "456", "789"] {
+            history.push(cypher.encode(raw)?);
+        }
+        assert_eq!(history.len(), 2);
+        // The oldest fell out of the history
+        assert!(!history.was_recently_used(&cypher, "123")?);
+        assert!(history.was_recently_used(&cypher, "456")?);
+        assert!(history.was_recently_used(&cypher, "789")?);
+        assert!(!history.was_recently_used(&cypher, "000")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_history_disabled() -> Result<()> {
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let mut history = PasswordHistory::new(0);
+        history.push(cypher.encode("123")?);
+        assert!(history.is_empty());
+        Ok(())
+    }
+}
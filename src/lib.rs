@@ -0,0 +1,4192 @@
+//! Password hashing and user accounts for web applications.
+//!
+//! ```
+//! use secure_web_applictions_in_rust::{PasswordCypherBuilder, UserBuilder};
+//!
+//! let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+//! let user = UserBuilder::default()
+//!     .id(1)
+//!     .username("someuser100".into())
+//!     .email("someuser100@protomail.com".into())
+//!     .password(cypher.encode("correct horse")?)
+//!     .language("en".parse()?)
+//!     .build()?;
+//! assert!(user.verify_password(&cypher, "correct horse")?);
+//! assert!(!user.verify_password(&cypher, "correct horsE")?);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeSet, HashSet},
+    fmt,
+    hash::{Hash, Hasher},
+    io::Read,
+    ops::RangeInclusive,
+    str::FromStr,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use argon2::{Algorithm, Argon2, AssociatedData, Block, Params, ParamsBuilder, Version};
+use base64ct::{Base64Unpadded, Encoding};
+use derive_builder::{Builder, UninitializedFieldError};
+use derive_getters::Getters;
+
+use rand::{rngs::OsRng, CryptoRng, Rng, RngCore};
+use strum::{EnumProperty, IntoEnumIterator};
+use strum_macros::{Display, EnumIter, EnumProperty, EnumString, IntoStaticStr};
+use subtle::ConstantTimeEq;
+use thiserror::Error;
+use unicode_normalization::UnicodeNormalization;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+use crate::{
+    audit::{AuditEvent, AuditSink},
+    history::PasswordHistory,
+    totp::TotpSecret,
+};
+
+pub mod audit;
+pub mod auth;
+#[cfg(feature = "bench")]
+pub mod bench;
+#[cfg(feature = "http")]
+pub mod breach;
+#[cfg(feature = "serde")]
+pub mod bundle;
+pub mod denylist;
+mod encoding;
+pub mod history;
+pub mod policy;
+pub mod rate_limit;
+pub mod recovery;
+pub mod registration;
+pub mod repository;
+pub mod session;
+pub mod token;
+pub mod totp;
+
+// iso639_2 is the terminology code, iso639_2b the bibliographic one where it differs
+#[derive(Debug, Display, Clone, PartialEq, Eq, EnumIter, EnumProperty)]
+pub enum Language {
+    #[strum(props(lang = "en", iso639_2 = "eng"))]
+    English,
+    #[strum(props(lang = "zh", iso639_2 = "zho", iso639_2b = "chi"))]
+    Chinese,
+    #[strum(props(lang = "es", iso639_2 = "spa"))]
+    Spanish,
+    #[strum(props(lang = "fr", iso639_2 = "fra", iso639_2b = "fre"))]
+    French,
+    #[strum(props(lang = "de", iso639_2 = "deu", iso639_2b = "ger"))]
+    German,
+    #[strum(props(lang = "ja", iso639_2 = "jpn"))]
+    Japanese,
+    #[strum(props(lang = "pt", iso639_2 = "por"))]
+    Portuguese,
+    #[strum(props(lang = "ru", iso639_2 = "rus"))]
+    Russian,
+    #[strum(props(lang = "ar", iso639_2 = "ara", dir = "rtl"))]
+    Arabic,
+    #[strum(props(lang = "ko", iso639_2 = "kor"))]
+    Korean,
+}
+
+/// The CLDR plural forms, a translation picks its message variant by these
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PluralCategory {
+    Zero,
+    One,
+    Two,
+    Few,
+    Many,
+    Other,
+}
+
+/// Writing direction, displays as the value for the HTML `dir` attribute
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash, IntoStaticStr)]
+#[strum(serialize_all = "lowercase")]
+pub enum TextDirection {
+    Ltr,
+    Rtl,
+}
+
+#[derive(Debug, Error)]
+pub enum ApplicationError {
+    #[error("Language Not Found")]
+    LanguageNotFound,
+    #[error("Password Hashing Failed: {0}")]
+    PasswordHash(String),
+    #[error("Invalid PHC String: {0}")]
+    PhcParse(String),
+    #[error("Invalid Argon2 Parameters: {0}")]
+    InvalidParams(String),
+    #[error("Invalid Password: {0}")]
+    InvalidPassword(PasswordViolation),
+    #[error("Invalid Email: {0}")]
+    InvalidEmail(EmailViolation),
+    #[error("Invalid Username: {0}")]
+    InvalidUsername(UsernameViolation),
+    #[error("Incorrect Password")]
+    IncorrectPassword,
+    #[error("User Not Found")]
+    UserNotFound,
+    #[error("Duplicate User: {0} already taken")]
+    DuplicateUser(UniqueField),
+    #[error("Database Error: {0}")]
+    Database(String),
+    #[error("Account Locked")]
+    AccountLocked,
+    #[error("Account Disabled")]
+    AccountDisabled,
+    #[error("Account Pending Verification")]
+    AccountPendingVerification,
+    #[error("Invalid Token")]
+    InvalidToken,
+    #[error("Expired Token")]
+    ExpiredToken,
+    #[error("Invalid TOTP Secret: {0}")]
+    InvalidTotpSecret(String),
+    #[error("Breach Check Failed: {0}")]
+    BreachCheck(String),
+    #[error("Password Was Used Recently")]
+    PasswordReused,
+    #[error("Invalid Encoding: {0}")]
+    Encoding(String),
+    #[error("Passwords Do Not Match")]
+    PasswordMismatch,
+    #[error("Invalid Region: {0}")]
+    InvalidRegion(String),
+    #[error("Unsupported Credential Bundle Version: {0}")]
+    UnsupportedBundleVersion(u32),
+    #[error("Validation Failed: {}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))]
+    Validation(Vec<ApplicationError>),
+    #[error("Insufficient Memory: could not allocate {0} KiB for hashing")]
+    InsufficientMemory(usize),
+    #[error(transparent)]
+    PasswordPolicy(#[from] policy::PasswordPolicyError),
+    #[error(transparent)]
+    UninitializedField(#[from] UninitializedFieldError),
+}
+
+impl Language {
+    /// Every supported language, e.g. for rendering a language dropdown
+    pub fn all() -> Vec<Language> {
+        Self::iter().collect()
+    }
+
+    /// True for right-to-left scripts, use it to set `dir="rtl"`
+    pub fn is_rtl(&self) -> bool {
+        self.direction() == TextDirection::Rtl
+    }
+
+    /// The script's writing direction, e.g. `format!("dir=\"{}\"", lang.direction())`
+    pub fn direction(&self) -> TextDirection {
+        match self.get_str("dir") {
+            Some("rtl") => TextDirection::Rtl,
+            _ => TextDirection::Ltr,
+        }
+    }
+
+    /// The language's name for itself, for language pickers
+    pub fn native_name(&self) -> &'static str {
+        match self {
+            Self::English => "English",
+            Self::Chinese => "中文",
+            Self::Spanish => "Español",
+            Self::French => "Français",
+            Self::German => "Deutsch",
+            Self::Japanese => "日本語",
+            Self::Portuguese => "Português",
+            Self::Russian => "Русский",
+            Self::Arabic => "العربية",
+            Self::Korean => "한국어",
+        }
+    }
+
+    /// The language's name written in `target`, e.g. German in French is "Allemand".
+    /// Pairs without a translation fall back to the English name.
+    pub fn display_name_in(&self, target: Language) -> String {
+        use Language::*;
+        if *self == target {
+            return self.native_name().into();
+        }
+        let name = match (target, self) {
+            (Spanish, English) => "Inglés",
+            (Spanish, Chinese) => "Chino",
+            (Spanish, French) => "Francés",
+            (Spanish, German) => "Alemán",
+            (Spanish, Japanese) => "Japonés",
+            (Spanish, Portuguese) => "Portugués",
+            (Spanish, Russian) => "Ruso",
+            (Spanish, Arabic) => "Árabe",
+            (Spanish, Korean) => "Coreano",
+            (French, English) => "Anglais",
+            (French, Chinese) => "Chinois",
+            (French, Spanish) => "Espagnol",
+            (French, German) => "Allemand",
+            (French, Japanese) => "Japonais",
+            (French, Portuguese) => "Portugais",
+            (French, Russian) => "Russe",
+            (French, Arabic) => "Arabe",
+            (French, Korean) => "Coréen",
+            (German, English) => "Englisch",
+            (German, Chinese) => "Chinesisch",
+            (German, Spanish) => "Spanisch",
+            (German, French) => "Französisch",
+            (German, Japanese) => "Japanisch",
+            (German, Portuguese) => "Portugiesisch",
+            (German, Russian) => "Russisch",
+            (German, Arabic) => "Arabisch",
+            (German, Korean) => "Koreanisch",
+            (Portuguese, English) => "Inglês",
+            (Portuguese, Chinese) => "Chinês",
+            (Portuguese, Spanish) => "Espanhol",
+            (Portuguese, French) => "Francês",
+            (Portuguese, German) => "Alemão",
+            (Portuguese, Japanese) => "Japonês",
+            (Portuguese, Russian) => "Russo",
+            (Portuguese, Arabic) => "Árabe",
+            (Portuguese, Korean) => "Coreano",
+            _ => return self.to_string(),
+        };
+        name.into()
+    }
+
+    /// Best default BCP-47 tag, for the HTML `lang` attribute and `Content-Language` header
+    pub fn bcp47(&self) -> &'static str {
+        match self {
+            Self::English => "en",
+            Self::Chinese => "zh-Hans",
+            Self::Spanish => "es",
+            Self::French => "fr",
+            Self::German => "de",
+            Self::Japanese => "ja",
+            Self::Portuguese => "pt",
+            Self::Russian => "ru",
+            Self::Arabic => "ar",
+            Self::Korean => "ko",
+        }
+    }
+
+    /// Picks the most preferred supported language from an `Accept-Language` header
+    /// e.g. `fr-CH, fr;q=0.9, en;q=0.8`, region subtags are ignored and malformed entries skipped
+    pub fn negotiate(header: &str) -> Option<Language> {
+        let mut best: Option<(f32, Language)> = None;
+        for (quality, language) in Self::accepted(header) {
+            if best.as_ref().is_none_or(|(q, _)| quality > *q) {
+                best = Some((quality, language));
+            }
+        }
+        best.map(|(_, language)| language)
+    }
+
+    // The supported languages of an Accept-Language header with their quality, q=0 is
+    // an explicit refusal so those are left out
+    fn accepted(header: &str) -> impl Iterator<Item = (f32, Language)> + '_ {
+        header.split(',').filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let tag = parts.next().unwrap_or_default().trim();
+            let quality = match parts.next().map(str::trim) {
+                None => 1.0,
+                Some(q) => match q.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) {
+                    Some(q) if (0.0..=1.0).contains(&q) => q,
+                    _ => return None,
+                },
+            };
+            let language = tag.parse::<Language>().ok()?;
+            (quality > 0.0).then_some((quality, language))
+        })
+    }
+
+    /// Languages to try, in order, when looking up a translation, starting with this one.
+    /// Only mutually intelligible languages fall back to each other, everything else goes
+    /// straight to English since it's the language translations are most likely to exist in.
+    pub fn fallback_chain(&self) -> Vec<Language> {
+        match self {
+            Self::English => vec![Self::English],
+            Self::Portuguese => vec![Self::Portuguese, Self::Spanish, Self::English],
+            Self::Spanish => vec![Self::Spanish, Self::Portuguese, Self::English],
+            language => vec![language.clone(), Self::English],
+        }
+    }
+
+    /// Parses a three-letter ISO 639-2 code in any casing, accepting both the B and T codes
+    /// e.g. "fre" and "fra"
+    pub fn from_iso639_2(code: &str) -> Result<Language> {
+        let code = code.to_lowercase();
+        let code = Some(code.as_str());
+        Self::iter()
+            .find(|l| l.get_str("iso639_2") == code || l.get_str("iso639_2b") == code)
+            .ok_or(ApplicationError::LanguageNotFound)
+    }
+
+    /// Which plural form a count takes, following the CLDR rules for whole numbers
+    pub fn plural_category(&self, n: u64) -> PluralCategory {
+        let (n10, n100) = (n % 10, n % 100);
+        match self {
+            Self::English | Self::German => match n {
+                1 => PluralCategory::One,
+                _ => PluralCategory::Other,
+            },
+            // 1 is singular in Spanish, 0 and 1 are in French and Portuguese, and all
+            // three use "many" for whole millions, e.g. "un millón de usuarios"
+            Self::Spanish | Self::French | Self::Portuguese => match n {
+                1 => PluralCategory::One,
+                0 if *self != Self::Spanish => PluralCategory::One,
+                n if n != 0 && n % 1_000_000 == 0 => PluralCategory::Many,
+                _ => PluralCategory::Other,
+            },
+            Self::Russian => match (n10, n100) {
+                (1, n100) if n100 != 11 => PluralCategory::One,
+                (2..=4, n100) if !(12..=14).contains(&n100) => PluralCategory::Few,
+                _ => PluralCategory::Many,
+            },
+            Self::Arabic => match (n, n100) {
+                (0, _) => PluralCategory::Zero,
+                (1, _) => PluralCategory::One,
+                (2, _) => PluralCategory::Two,
+                (_, 3..=10) => PluralCategory::Few,
+                (_, 11..=99) => PluralCategory::Many,
+                _ => PluralCategory::Other,
+            },
+            Self::Chinese | Self::Japanese | Self::Korean => PluralCategory::Other,
+        }
+    }
+}
+
+pub type Result<T, E = ApplicationError> = std::result::Result<T, E>;
+
+impl From<argon2::Error> for ApplicationError {
+    fn from(e: argon2::Error) -> Self {
+        Self::PasswordHash(e.to_string())
+    }
+}
+
+impl From<bcrypt::BcryptError> for ApplicationError {
+    fn from(e: bcrypt::BcryptError) -> Self {
+        Self::PasswordHash(e.to_string())
+    }
+}
+
+impl ApplicationError {
+    /// The error text for showing to an end user, walking the language's fallback chain
+    /// until a translation is found. English is always the `Display` text.
+    pub fn localized_message(&self, lang: Language) -> String {
+        lang.fallback_chain()
+            .iter()
+            .find_map(|lang| self.translation(lang))
+            .unwrap_or_else(|| self.to_string())
+    }
+
+    // Only errors a user can act on are translated, internal ones stay in English.
+    // Errors with details are translated whole by their detail.
+    fn translation(&self, lang: &Language) -> Option<String> {
+        use Language::{French, German, Spanish};
+        let message = match (lang, self) {
+            (_, Self::InvalidPassword(v)) => return v.translation(lang),
+            (_, Self::InvalidEmail(v)) => return v.translation(lang),
+            (_, Self::InvalidUsername(v)) => return v.translation(lang),
+            (_, Self::DuplicateUser(field)) => return field.translation(lang),
+            (Spanish, Self::LanguageNotFound) => "Idioma no encontrado".into(),
+            (Spanish, Self::IncorrectPassword) => "Contraseña incorrecta".into(),
+            (Spanish, Self::UserNotFound) => "Usuario no encontrado".into(),
+            (Spanish, Self::AccountLocked) => "Cuenta bloqueada".into(),
+            (Spanish, Self::AccountDisabled) => "Cuenta desactivada".into(),
+            (Spanish, Self::AccountPendingVerification) => {
+                "Cuenta pendiente de verificación".into()
+            }
+            (Spanish, Self::InvalidToken) => "Token no válido".into(),
+            (Spanish, Self::ExpiredToken) => "Token caducado".into(),
+            (Spanish, Self::PasswordReused) => "La contraseña se usó recientemente".into(),
+            (Spanish, Self::PasswordMismatch) => "Las contraseñas no coinciden".into(),
+            (French, Self::LanguageNotFound) => "Langue introuvable".into(),
+            (French, Self::IncorrectPassword) => "Mot de passe incorrect".into(),
+            (French, Self::UserNotFound) => "Utilisateur introuvable".into(),
+            (French, Self::AccountLocked) => "Compte verrouillé".into(),
+            (French, Self::AccountDisabled) => "Compte désactivé".into(),
+            (French, Self::AccountPendingVerification) => {
+                "Compte en attente de vérification".into()
+            }
+            (French, Self::InvalidToken) => "Jeton invalide".into(),
+            (French, Self::ExpiredToken) => "Jeton expiré".into(),
+            (French, Self::PasswordReused) => "Mot de passe utilisé récemment".into(),
+            (French, Self::PasswordMismatch) => "Les mots de passe ne correspondent pas".into(),
+            (German, Self::LanguageNotFound) => "Sprache nicht gefunden".into(),
+            (German, Self::IncorrectPassword) => "Falsches Passwort".into(),
+            (German, Self::UserNotFound) => "Benutzer nicht gefunden".into(),
+            (German, Self::AccountLocked) => "Konto gesperrt".into(),
+            (German, Self::AccountDisabled) => "Konto deaktiviert".into(),
+            (German, Self::AccountPendingVerification) => "Konto wartet auf Bestätigung".into(),
+            (German, Self::InvalidToken) => "Ungültiges Token".into(),
+            (German, Self::ExpiredToken) => "Abgelaufenes Token".into(),
+            (German, Self::PasswordReused) => "Passwort wurde kürzlich verwendet".into(),
+            (German, Self::PasswordMismatch) => "Passwörter stimmen nicht überein".into(),
+            _ => return None,
+        };
+        Some(message)
+    }
+}
+
+/// Why a password couldn't be encoded, the rules a `PasswordPolicy` adds are `PolicyViolation`s
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum PasswordViolation {
+    #[error("must not be empty")]
+    Empty,
+    #[error("must be at most {0} bytes")]
+    TooLong(usize),
+    #[error("could not be read: {0}")]
+    Unreadable(String),
+    #[error("must be valid UTF-8")]
+    NotUtf8,
+    #[error("temporary passwords must be at least {0} characters")]
+    TemporaryTooShort(usize),
+}
+
+impl PasswordViolation {
+    // The read error's detail is left out, it's only useful in logs
+    fn translation(&self, lang: &Language) -> Option<String> {
+        use Language::{French, German, Spanish};
+        let message = match (lang, self) {
+            (Spanish, Self::Empty) => "La contraseña no puede estar vacía".into(),
+            (Spanish, Self::TooLong(n)) => {
+                format!("La contraseña debe tener como máximo {n} bytes")
+            }
+            (Spanish, Self::Unreadable(_)) => "No se pudo leer la contraseña".into(),
+            (Spanish, Self::NotUtf8) => "La contraseña debe ser UTF-8 válido".into(),
+            (Spanish, Self::TemporaryTooShort(n)) => {
+                format!("Las contraseñas temporales deben tener al menos {n} caracteres")
+            }
+            (French, Self::Empty) => "Le mot de passe ne peut pas être vide".into(),
+            (French, Self::TooLong(n)) => {
+                format!("Le mot de passe doit contenir au plus {n} octets")
+            }
+            (French, Self::Unreadable(_)) => "Impossible de lire le mot de passe".into(),
+            (French, Self::NotUtf8) => "Le mot de passe doit être en UTF-8 valide".into(),
+            (French, Self::TemporaryTooShort(n)) => {
+                format!("Les mots de passe temporaires doivent contenir au moins {n} caractères")
+            }
+            (German, Self::Empty) => "Das Passwort darf nicht leer sein".into(),
+            (German, Self::TooLong(n)) => {
+                format!("Das Passwort darf höchstens {n} Bytes lang sein")
+            }
+            (German, Self::Unreadable(_)) => "Das Passwort konnte nicht gelesen werden".into(),
+            (German, Self::NotUtf8) => "Das Passwort muss gültiges UTF-8 sein".into(),
+            (German, Self::TemporaryTooShort(n)) => {
+                format!("Temporäre Passwörter müssen mindestens {n} Zeichen lang sein")
+            }
+            _ => return None,
+        };
+        Some(message)
+    }
+}
+
+/// A user field no two users may share
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum UniqueField {
+    Id,
+    Username,
+    Email,
+}
+
+impl UniqueField {
+    fn translation(&self, lang: &Language) -> Option<String> {
+        use Language::{French, German, Spanish};
+        let message = match (lang, self) {
+            (Spanish, Self::Id) => "El identificador ya está en uso",
+            (Spanish, Self::Username) => "El nombre de usuario ya está en uso",
+            (Spanish, Self::Email) => "El correo electrónico ya está en uso",
+            (French, Self::Id) => "L'identifiant est déjà pris",
+            (French, Self::Username) => "Le nom d'utilisateur est déjà pris",
+            (French, Self::Email) => "L'e-mail est déjà pris",
+            (German, Self::Id) => "Die ID ist bereits vergeben",
+            (German, Self::Username) => "Der Benutzername ist bereits vergeben",
+            (German, Self::Email) => "Die E-Mail ist bereits vergeben",
+            _ => return None,
+        };
+        Some(message.into())
+    }
+}
+
+impl FromStr for Language {
+    type Err = ApplicationError;
+
+    // Accepts any casing and ignores region/script subtags, e.g. "EN" or "en-GB"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let primary = s.split('-').next().unwrap_or_default().to_lowercase();
+        Self::iter()
+            .find(|l| l.get_str("lang") == Some(primary.as_str()))
+            .ok_or(Self::Err::LanguageNotFound)
+    }
+}
+
+/// Serializes as the short `lang` code, e.g. "en"
+#[cfg(feature = "serde")]
+impl serde::Serialize for Language {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let lang = self
+            .get_str("lang")
+            .ok_or_else(|| serde::ser::Error::custom("missing lang code"))?;
+        serializer.serialize_str(lang)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Language {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let lang = String::deserialize(deserializer)?;
+        lang.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Matches on the primary language subtag, e.g. "pt-BR" is Portuguese
+#[cfg(feature = "language-tags")]
+impl TryFrom<&language_tags::LanguageTag> for Language {
+    type Error = ApplicationError;
+
+    fn try_from(tag: &language_tags::LanguageTag) -> Result<Self> {
+        tag.primary_language().parse()
+    }
+}
+
+/// The same tag as `Language::bcp47`
+#[cfg(feature = "language-tags")]
+impl From<Language> for language_tags::LanguageTag {
+    fn from(language: Language) -> Self {
+        Self::parse(language.bcp47()).expect("bcp47 tags are well formed")
+    }
+}
+
+/// An ISO 3166-1 alpha-2 country or region code, e.g. "US", always upper case
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Region([u8; 2]);
+
+impl Region {
+    pub fn as_str(&self) -> &str {
+        // Only ever built from two ASCII letters
+        std::str::from_utf8(&self.0).unwrap_or_default()
+    }
+}
+
+impl FromStr for Region {
+    type Err = ApplicationError;
+
+    // Only the shape is checked, not that the code is assigned
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.as_bytes() {
+            &[a, b] if a.is_ascii_alphabetic() && b.is_ascii_alphabetic() => {
+                Ok(Self([a.to_ascii_uppercase(), b.to_ascii_uppercase()]))
+            }
+            _ => Err(ApplicationError::InvalidRegion(s.into())),
+        }
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A language with an optional region, e.g. en-US vs en-GB for formatting dates and
+/// numbers. Translations are looked up by the bare `language`.
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+pub struct Locale {
+    language: Language,
+    region: Option<Region>,
+}
+
+impl Locale {
+    pub fn new(language: Language, region: Option<Region>) -> Self {
+        Self { language, region }
+    }
+}
+
+impl FromStr for Locale {
+    type Err = ApplicationError;
+
+    // Accepts "en", "en-US", "en_us" and skips a script subtag as in "zh-Hans-CN"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut subtags = s.split(['-', '_']);
+        let language = subtags.next().unwrap_or_default().parse()?;
+        let region = subtags
+            .find(|subtag| subtag.len() != 4)
+            .map(str::parse)
+            .transpose()?;
+        if let Some(extra) = subtags.next() {
+            return Err(ApplicationError::InvalidRegion(extra.into()));
+        }
+        Ok(Self { language, region })
+    }
+}
+
+/// The BCP-47 style tag, e.g. "pt-BR"
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.language.get_str("lang").unwrap_or_default())?;
+        match &self.region {
+            Some(region) => write!(f, "-{region}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Generations of the stored credential format, newer versions change the defaults
+/// while older credentials keep verifying until they're re-encoded
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CredentialVersion {
+    /// Anything but Argon2id on Argon2 version 0x13, e.g. hashes from older Argon2 libraries
+    V1,
+    /// Argon2id, Argon2 version 0x13
+    V2,
+}
+
+impl CredentialVersion {
+    /// What new passwords are encoded as by default
+    pub const CURRENT: Self = Self::V2;
+}
+
+/// The Argon2 settings a password was encoded with, without any of the secret bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Getters)]
+pub struct Argon2ParamsSummary {
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+    variant: Algorithm,
+}
+
+impl fmt::Display for Argon2ParamsSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} m={},t={},p={}",
+            self.variant, self.memory_kib, self.iterations, self.parallelism
+        )
+    }
+}
+
+// Wipe the salt and hash from memory whenever a Password is dropped
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct Password {
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+    // The Argon2 settings that produced the hash
+    #[zeroize(skip)]
+    algorithm: Algorithm,
+    #[zeroize(skip)]
+    version: Version,
+    #[zeroize(skip)]
+    params: Params,
+}
+
+impl Password {
+    // Stands in for a real password in copies of a user handed to logging and analytics,
+    // with no salt or hash it can never verify
+    fn redacted() -> Self {
+        Self {
+            salt: Vec::new(),
+            hash: Vec::new(),
+            algorithm: Algorithm::default(),
+            version: Version::default(),
+            params: Params::default(),
+        }
+    }
+
+    /// True for the placeholder that `User::clone_for_audit` puts in place of the hash
+    pub fn is_redacted(&self) -> bool {
+        self.hash.is_empty()
+    }
+
+    /// The settings that produced the hash, e.g. for logging credential upgrades
+    pub fn params(&self) -> Argon2ParamsSummary {
+        Argon2ParamsSummary {
+            memory_kib: self.params.m_cost(),
+            iterations: self.params.t_cost(),
+            parallelism: self.params.p_cost(),
+            variant: self.algorithm,
+        }
+    }
+
+    /// The format generation the password was stored with
+    pub fn version(&self) -> CredentialVersion {
+        if self.algorithm == Algorithm::Argon2id && self.version == Version::V0x13 {
+            CredentialVersion::V2
+        } else {
+            CredentialVersion::V1
+        }
+    }
+
+    /// Encodes the password in the PHC string format understood by other Argon2 libraries
+    /// e.g. `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`
+    pub fn to_phc_string(&self) -> String {
+        format!(
+            "${}$v={}$m={},t={},p={}${}${}",
+            self.algorithm,
+            u32::from(self.version),
+            self.params.m_cost(),
+            self.params.t_cost(),
+            self.params.p_cost(),
+            Base64Unpadded::encode_string(&self.salt),
+            Base64Unpadded::encode_string(&self.hash),
+        )
+    }
+
+    /// Parses a password previously exported with `to_phc_string`
+    pub fn from_phc_string(s: &str) -> Result<Password> {
+        let err = |msg: &str| ApplicationError::PhcParse(msg.into());
+        let segments: Vec<&str> = s.split('$').collect();
+        let [empty, algorithm, version, params, salt, hash] = segments[..] else {
+            return Err(err("expected 5 '$' delimited segments"));
+        };
+        if !empty.is_empty() {
+            return Err(err("must start with '$'"));
+        }
+        let algorithm = Algorithm::new(algorithm).map_err(|_| err("unsupported algorithm id"))?;
+        let version = version
+            .strip_prefix("v=")
+            .and_then(|v| v.parse::<u32>().ok())
+            .and_then(|v| Version::try_from(v).ok())
+            .ok_or_else(|| err("unsupported version"))?;
+        let (mut m_cost, mut t_cost, mut p_cost) = (None, None, None);
+        for param in params.split(',') {
+            let (key, value) = param
+                .split_once('=')
+                .ok_or_else(|| err("malformed parameter"))?;
+            let value = value
+                .parse::<u32>()
+                .map_err(|_| err("malformed parameter value"))?;
+            match key {
+                "m" => m_cost = Some(value),
+                "t" => t_cost = Some(value),
+                "p" => p_cost = Some(value),
+                _ => return Err(err("unknown parameter")),
+            }
+        }
+        let (Some(m_cost), Some(t_cost), Some(p_cost)) = (m_cost, t_cost, p_cost) else {
+            return Err(err("missing parameter"));
+        };
+        let params = Params::new(m_cost, t_cost, p_cost, None).map_err(|e| err(&e.to_string()))?;
+        // The lengths aren't stored separately, they're whatever was encoded
+        let salt = decode_b64_in(salt, SALT_LENGTHS).ok_or_else(|| err("malformed salt"))?;
+        let hash = decode_b64_in(hash, OUTPUT_LENGTHS).ok_or_else(|| err("malformed hash"))?;
+        Ok(Password {
+            salt,
+            hash,
+            algorithm,
+            version,
+            params,
+        })
+    }
+}
+
+fn decode_b64_in(src: &str, lengths: RangeInclusive<usize>) -> Option<Vec<u8>> {
+    Base64Unpadded::decode_vec(src)
+        .ok()
+        .filter(|bytes| lengths.contains(&bytes.len()))
+}
+
+// Lengths in bytes, Argon2 wants salts of at least 8 and outputs shorter than 16 bytes
+// are too easy to collide
+const DEFAULT_SALT_LENGTH: usize = 16;
+const DEFAULT_OUTPUT_LENGTH: usize = 32;
+const SALT_LENGTHS: RangeInclusive<usize> = argon2::MIN_SALT_LEN..=64;
+const OUTPUT_LENGTHS: RangeInclusive<usize> = 16..=64;
+
+// Memory costs in KiB, Argon2 needs 8 KiB per lane and past a few GiB the setting is
+// almost certainly a units mistake that would exhaust the server
+const MIN_MEMORY_KIB: u32 = Params::MIN_M_COST;
+const DEFAULT_MAX_MEMORY_KIB: u32 = 4 * 1024 * 1024;
+
+// Hashing cost grows with input size, so huge passwords are rejected rather than hashed
+const DEFAULT_MAX_PASSWORD_LENGTH: usize = 1024;
+
+// Temporary passwords get read out of emails and retyped, so no 0/O/o, 1/l/I look-alikes.
+// 56 symbols at 12 characters is about 70 bits.
+const TEMPORARY_PASSWORD_ALPHABET: &[u8] =
+    b"23456789abcdefghijkmnpqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ";
+const MIN_TEMPORARY_PASSWORD_LENGTH: usize = 12;
+
+#[derive(Clone)]
+pub struct PasswordCypher<'a> {
+    argon: Argon2<'a>,
+    algorithm: Algorithm,
+    version: Version,
+    pepper: Option<&'a [u8]>,
+    max_password_length: usize,
+    salt_length: usize,
+    output_length: usize,
+}
+
+impl Default for PasswordCypher<'_> {
+    fn default() -> Self {
+        Self::new(Algorithm::default(), Version::default(), Params::default())
+    }
+}
+
+fn new_argon2(
+    pepper: Option<&[u8]>,
+    algorithm: Algorithm,
+    version: Version,
+    params: Params,
+) -> Result<Argon2<'_>> {
+    match pepper {
+        Some(secret) => Argon2::new_with_secret(secret, algorithm, version, params)
+            .map_err(|e| ApplicationError::InvalidParams(e.to_string())),
+        None => Ok(Argon2::new(algorithm, version, params)),
+    }
+}
+
+// Argon2 would allocate its working memory infallibly, aborting the process when the
+// allocator refuses. Reserving it here turns that refusal into InsufficientMemory, as is
+// argon2's own MemoryTooMuch. With overcommit the reservation can still succeed and the
+// kernel's OOM killer step in later, only a memory cost the host can afford avoids that.
+fn hash_into(argon: &Argon2, raw_text: &[u8], salt: &[u8], out: &mut [u8]) -> Result<()> {
+    let block_count = argon.params().block_count();
+    let kib = block_count * Block::SIZE / 1024;
+    let mut blocks = Vec::new();
+    blocks
+        .try_reserve_exact(block_count)
+        .map_err(|_| ApplicationError::InsufficientMemory(kib))?;
+    blocks.resize(block_count, Block::default());
+    argon
+        .hash_password_into_with_memory(raw_text, salt, out, &mut blocks)
+        .map_err(|e| hashing_error(e, kib))
+}
+
+fn hashing_error(e: argon2::Error, kib: usize) -> ApplicationError {
+    match e {
+        argon2::Error::MemoryTooMuch => ApplicationError::InsufficientMemory(kib),
+        e => e.into(),
+    }
+}
+
+fn verify_using(
+    argon: &Argon2,
+    password: &Password,
+    raw_text: impl Into<SecretString>,
+) -> Result<bool> {
+    if password.is_redacted() {
+        return Ok(false);
+    }
+    let raw_text = normalize(raw_text.into().expose_secret());
+    // Hash to the stored length, whatever the cypher's output length is now
+    let mut hash = Zeroizing::new(vec![0; password.hash.len()]);
+    hash_into(argon, raw_text.as_bytes(), &password.salt, &mut hash)?;
+    // Compare in constant time so response timing doesn't leak hash prefixes
+    Ok(password.hash.ct_eq(&hash).into())
+}
+
+// The same params with `ad` mixed into the hash
+fn params_with_ad(params: &Params, ad: &[u8]) -> Result<Params> {
+    let data = AssociatedData::new(ad).map_err(|_| {
+        ApplicationError::InvalidParams(format!(
+            "associated data must be at most {} bytes",
+            AssociatedData::MAX_LEN
+        ))
+    })?;
+    let mut builder = ParamsBuilder::new();
+    builder
+        .m_cost(params.m_cost())
+        .t_cost(params.t_cost())
+        .p_cost(params.p_cost())
+        .data(data);
+    if let Some(len) = params.output_len() {
+        builder.output_len(len);
+    }
+    builder
+        .build()
+        .map_err(|e| ApplicationError::InvalidParams(e.to_string()))
+}
+
+// Salt for dummy verifications, what's hashed with it is thrown away so it needn't be secret
+const DUMMY_SALT: [u8; 16] = *b"dummy-verify-slt";
+
+// Calibration never goes beyond 256 MiB per hash so it can't exhaust the machine
+const CALIBRATION_MAX_M_COST: u32 = 256 * 1024;
+const CALIBRATION_MAX_T_COST: u32 = 64;
+
+/// A raw password, it's wiped from memory on drop and Debug never prints it
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(secret: String) -> Self {
+        Self(secret)
+    }
+
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        Self(secret)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        Self(secret.to_owned())
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+// The same password typed on different devices can arrive composed or decomposed,
+// normalizing before hashing makes them verify against each other
+fn normalize(raw_text: &str) -> Zeroizing<String> {
+    Zeroizing::new(raw_text.nfkc().collect())
+}
+
+impl<'a> PasswordCypher<'a> {
+    pub fn new(algorithm: Algorithm, version: Version, params: Params) -> Self {
+        Self {
+            argon: Argon2::new(algorithm, version, params),
+            algorithm,
+            version,
+            pepper: None,
+            max_password_length: DEFAULT_MAX_PASSWORD_LENGTH,
+            salt_length: DEFAULT_SALT_LENGTH,
+            output_length: DEFAULT_OUTPUT_LENGTH,
+        }
+    }
+
+    pub fn encode(&self, raw_text: impl Into<SecretString>) -> Result<Password> {
+        self.encode_with_rng(raw_text, &mut OsRng)
+    }
+
+    /// Encodes with salts drawn from `rng`, so tests can seed it for reproducible hashes.
+    /// Use `encode` in production, a predictable salt defeats the point of having one!
+    pub fn encode_with_rng<R: RngCore + CryptoRng>(
+        &self,
+        raw_text: impl Into<SecretString>,
+        rng: &mut R,
+    ) -> Result<Password> {
+        self.encode_using(&self.argon, raw_text, rng)
+    }
+
+    /// Encodes with `ad` (up to 32 bytes, e.g. the user's id) mixed into the hash, binding
+    /// it to that context. The associated data isn't stored, `try_password_with_ad` has to
+    /// be given the same bytes again.
+    pub fn encode_with_ad(&self, raw_text: impl Into<SecretString>, ad: &[u8]) -> Result<Password> {
+        let argon = new_argon2(
+            self.pepper,
+            self.algorithm,
+            self.version,
+            params_with_ad(self.params(), ad)?,
+        )?;
+        self.encode_using(&argon, raw_text, &mut OsRng)
+    }
+
+    fn encode_using<R: RngCore + CryptoRng>(
+        &self,
+        argon: &Argon2,
+        raw_text: impl Into<SecretString>,
+        rng: &mut R,
+    ) -> Result<Password> {
+        let raw_text = normalize(raw_text.into().expose_secret());
+        if raw_text.is_empty() {
+            return Err(ApplicationError::InvalidPassword(PasswordViolation::Empty));
+        }
+        if raw_text.len() > self.max_password_length {
+            return Err(ApplicationError::InvalidPassword(
+                PasswordViolation::TooLong(self.max_password_length),
+            ));
+        }
+        let mut salt = vec![0; self.salt_length];
+        let mut hash = vec![0; self.output_length];
+        rng.fill_bytes(&mut salt);
+        hash_into(argon, raw_text.as_bytes(), &salt, &mut hash)?;
+        Ok(Password {
+            salt,
+            hash,
+            algorithm: self.algorithm,
+            version: self.version,
+            params: self.argon.params().clone(),
+        })
+    }
+
+    pub fn try_password(
+        &self,
+        password: &Password,
+        raw_text: impl Into<SecretString>,
+    ) -> Result<bool> {
+        let argon = self.argon_for(password)?;
+        verify_using(&argon, password, raw_text)
+    }
+
+    /// Verifies a password from `encode_with_ad`, any other `ad` doesn't match
+    pub fn try_password_with_ad(
+        &self,
+        password: &Password,
+        raw_text: impl Into<SecretString>,
+        ad: &[u8],
+    ) -> Result<bool> {
+        let argon = new_argon2(
+            self.pepper,
+            password.algorithm,
+            password.version,
+            params_with_ad(&password.params, ad)?,
+        )?;
+        verify_using(&argon, password, raw_text)
+    }
+
+    /// Moves a password to a new pepper, run it at login while the raw password is at hand.
+    ///
+    /// It's verified under `old_pepper` and re-encoded under `new_pepper` with this
+    /// cypher's current settings, whatever pepper the cypher itself has. A wrong password
+    /// or old pepper is an `IncorrectPassword` error.
+    pub fn rehash_with_new_pepper(
+        &self,
+        old_pepper: &[u8],
+        new_pepper: &[u8],
+        password: &Password,
+        raw_text: impl Into<SecretString>,
+    ) -> Result<Password> {
+        let raw_text = raw_text.into();
+        if !self
+            .with_pepper_override(old_pepper)?
+            .try_password(password, raw_text.clone())?
+        {
+            return Err(ApplicationError::IncorrectPassword);
+        }
+        self.with_pepper_override(new_pepper)?.encode(raw_text)
+    }
+
+    fn with_pepper_override<'b>(&self, pepper: &'b [u8]) -> Result<PasswordCypher<'b>>
+    where
+        'a: 'b,
+    {
+        let mut cypher: PasswordCypher<'b> = self.clone();
+        cypher.argon = new_argon2(
+            Some(pepper),
+            self.algorithm,
+            self.version,
+            self.params().clone(),
+        )?;
+        cypher.pepper = Some(pepper);
+        Ok(cypher)
+    }
+
+    /// Encodes a password read from e.g. stdin or a file, a trailing newline isn't part of
+    /// it. Reading stops just past the length limit so a huge input can't exhaust memory.
+    pub fn encode_reader<R: Read>(&self, r: &mut R) -> Result<Password> {
+        // Room for the limit and a trailing "\r\n", anything longer is too long either way
+        let limit = self.max_password_length as u64 + 2;
+        let mut bytes = Zeroizing::new(Vec::new());
+        r.take(limit).read_to_end(&mut bytes).map_err(|e| {
+            ApplicationError::InvalidPassword(PasswordViolation::Unreadable(e.to_string()))
+        })?;
+        if bytes.ends_with(b"\n") {
+            bytes.pop();
+            if bytes.ends_with(b"\r") {
+                bytes.pop();
+            }
+        }
+        if bytes.len() > self.max_password_length {
+            return Err(ApplicationError::InvalidPassword(
+                PasswordViolation::TooLong(self.max_password_length),
+            ));
+        }
+        let raw_text = std::str::from_utf8(&bytes)
+            .map_err(|_| ApplicationError::InvalidPassword(PasswordViolation::NotUtf8))?;
+        self.encode(raw_text)
+    }
+
+    /// A random password of `len` characters for a newly provisioned account, returned
+    /// in clear text to send to the user once, alongside its encoding to store
+    pub fn generate_temporary(&self, len: usize) -> Result<(String, Password)> {
+        if len < MIN_TEMPORARY_PASSWORD_LENGTH {
+            return Err(ApplicationError::InvalidPassword(
+                PasswordViolation::TemporaryTooShort(MIN_TEMPORARY_PASSWORD_LENGTH),
+            ));
+        }
+        let raw: String = (0..len)
+            .map(|_| {
+                char::from(
+                    TEMPORARY_PASSWORD_ALPHABET
+                        [OsRng.gen_range(0..TEMPORARY_PASSWORD_ALPHABET.len())],
+                )
+            })
+            .collect();
+        let password = self.encode(raw.clone())?;
+        Ok((raw, password))
+    }
+
+    // The cypher's own Argon2 is built once and borrowed for every password encoded with
+    // its settings. Passwords encoded with other settings are verified with the settings
+    // they were encoded with so that they keep working until they are rehashed.
+    fn argon_for(&self, password: &Password) -> Result<Cow<'_, Argon2<'a>>> {
+        if !self.needs_rehash(password) {
+            return Ok(Cow::Borrowed(&self.argon));
+        }
+        Ok(Cow::Owned(new_argon2(
+            self.pepper,
+            password.algorithm,
+            password.version,
+            password.params.clone(),
+        )?))
+    }
+
+    /// Performs a real hash of the raw password and discards the result.
+    ///
+    /// Run this when a login fails because the username doesn't exist, so that response
+    /// takes as long as a wrong password would and can't be used to enumerate accounts:
+    /// ```ignore
+    /// match find_user(username) {
+    ///     Some(user) => user.try_password_with(&cypher, raw)?,
+    ///     None => {
+    ///         cypher.dummy_verify(raw)?;
+    ///         false
+    ///     }
+    /// }
+    /// ```
+    pub fn dummy_verify(&self, raw_text: impl Into<SecretString>) -> Result<()> {
+        let raw_text = normalize(raw_text.into().expose_secret());
+        let mut hash = vec![0; self.output_length];
+        hash_into(&self.argon, raw_text.as_bytes(), &DUMMY_SALT, &mut hash)?;
+        std::hint::black_box(hash.iter().all(|&b| b == 0));
+        Ok(())
+    }
+
+    /// The Argon2 parameters used to encode new passwords
+    pub fn params(&self) -> &Params {
+        self.argon.params()
+    }
+
+    /// Builds a cypher from `ARGON2_MEMORY_KIB`, `ARGON2_ITERATIONS` and
+    /// `ARGON2_PARALLELISM`, unset variables keep the library defaults
+    pub fn from_env() -> Result<Self> {
+        let mut builder = PasswordCypherBuilder::default();
+        if let Some(kib) = env_u32("ARGON2_MEMORY_KIB")? {
+            builder.memory_cost(kib);
+        }
+        if let Some(iterations) = env_u32("ARGON2_ITERATIONS")? {
+            builder.time_cost(iterations);
+        }
+        if let Some(lanes) = env_u32("ARGON2_PARALLELISM")? {
+            builder.parallelism(lanes);
+        }
+        builder.build()
+    }
+
+    /// Benchmarks encoding at increasing costs until a single hash takes roughly the target
+    /// duration. Memory is raised first, up to a cap, then the iteration count.
+    /// The chosen configuration is available through `params` for logging.
+    pub fn calibrate(target: Duration) -> Result<Self> {
+        let time_encode = |m_cost, t_cost| -> Result<_> {
+            let cypher = PasswordCypherBuilder::default()
+                .memory_cost(m_cost)
+                .time_cost(t_cost)
+                .build()?;
+            let start = Instant::now();
+            cypher.encode("calibration")?;
+            Ok((cypher, start.elapsed()))
+        };
+        let mut m_cost = Params::DEFAULT_M_COST;
+        let mut t_cost = 1;
+        let (mut cypher, mut elapsed) = time_encode(m_cost, t_cost)?;
+        while elapsed < target && m_cost * 2 <= CALIBRATION_MAX_M_COST {
+            m_cost *= 2;
+            (cypher, elapsed) = time_encode(m_cost, t_cost)?;
+        }
+        while elapsed < target && t_cost < CALIBRATION_MAX_T_COST {
+            t_cost += 1;
+            (cypher, elapsed) = time_encode(m_cost, t_cost)?;
+        }
+        Ok(cypher)
+    }
+
+    /// True when the password was encoded with different settings than this cypher uses,
+    /// callers should re-encode it on the next successful login
+    pub fn needs_rehash(&self, password: &Password) -> bool {
+        password.algorithm != self.algorithm
+            || password.version != self.version
+            || password.params != *self.argon.params()
+            || password.salt.len() != self.salt_length
+            || password.hash.len() != self.output_length
+    }
+
+    /// Verifies the password and, if it was encoded with outdated settings, re-encodes it
+    /// with the current ones. Only `AcceptedRehashed` needs saving.
+    pub fn verify_and_upgrade(&self, password: &Password, raw: &str) -> Result<VerifyOutcome> {
+        if !self.try_password(password, raw)? {
+            return Ok(VerifyOutcome::Rejected);
+        }
+        if self.needs_rehash(password) {
+            return Ok(VerifyOutcome::AcceptedRehashed(self.encode(raw)?));
+        }
+        Ok(VerifyOutcome::Accepted)
+    }
+}
+
+/// What `PasswordCypher::verify_and_upgrade` made of a login attempt
+#[derive(Debug, Clone)]
+pub enum VerifyOutcome {
+    Rejected,
+    Accepted,
+    /// The password was right, store this re-encoded replacement
+    AcceptedRehashed(Password),
+}
+
+/// A password hashing backend, `C` is the credential it stores and verifies against.
+/// Argon2 via PasswordCypher is the default, other backends exist for migrating legacy hashes.
+pub trait PasswordHasher<C = Password> {
+    fn encode(&self, raw_text: &SecretString) -> Result<C>;
+    fn verify(&self, credential: &C, raw_text: &SecretString) -> Result<bool>;
+}
+
+impl PasswordHasher for PasswordCypher<'_> {
+    fn encode(&self, raw_text: &SecretString) -> Result<Password> {
+        PasswordCypher::encode(self, raw_text.clone())
+    }
+
+    fn verify(&self, password: &Password, raw_text: &SecretString) -> Result<bool> {
+        self.try_password(password, raw_text.clone())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashScheme {
+    Argon2,
+    Bcrypt,
+    Scrypt,
+}
+
+/// Which scheme produced a stored credential, judged by its prefix
+pub fn detect_scheme(stored: &str) -> Option<HashScheme> {
+    if stored.starts_with("$argon2") {
+        Some(HashScheme::Argon2)
+    } else if ["$2a$", "$2b$", "$2x$", "$2y$"]
+        .iter()
+        .any(|prefix| stored.starts_with(prefix))
+    {
+        Some(HashScheme::Bcrypt)
+    } else if stored.starts_with("$scrypt$") {
+        Some(HashScheme::Scrypt)
+    } else {
+        None
+    }
+}
+
+/// Verifies bcrypt hashes carried over from legacy apps (PHP, Rails, etc.) so they can be
+/// rehashed with a PasswordCypher on the next successful login
+#[derive(Debug, Clone, Copy)]
+pub struct BcryptCypher {
+    cost: u32,
+}
+
+impl Default for BcryptCypher {
+    fn default() -> Self {
+        Self::new(bcrypt::DEFAULT_COST)
+    }
+}
+
+impl BcryptCypher {
+    pub fn new(cost: u32) -> Self {
+        Self { cost }
+    }
+
+    pub fn encode(&self, raw_text: impl Into<SecretString>) -> Result<String> {
+        // bcrypt only looks at the first 72 bytes, refuse rather than silently truncate
+        bcrypt::non_truncating_hash(raw_text.into().expose_secret(), self.cost)
+            .map_err(ApplicationError::from)
+    }
+
+    pub fn try_password(&self, stored: &str, raw_text: impl Into<SecretString>) -> Result<bool> {
+        if detect_scheme(stored) != Some(HashScheme::Bcrypt) {
+            return Err(ApplicationError::PasswordHash("not a bcrypt hash".into()));
+        }
+        // Legacy hashes are of the raw bytes, so unlike PasswordCypher there's no normalization
+        Ok(bcrypt::verify(raw_text.into().expose_secret(), stored)?)
+    }
+}
+
+impl PasswordHasher<String> for BcryptCypher {
+    fn encode(&self, raw_text: &SecretString) -> Result<String> {
+        BcryptCypher::encode(self, raw_text.clone())
+    }
+
+    fn verify(&self, stored: &String, raw_text: &SecretString) -> Result<bool> {
+        self.try_password(stored, raw_text.clone())
+    }
+}
+
+/// Verifies scrypt PHC strings, e.g. `$scrypt$ln=17,r=8,p=1$...`, carried over from other
+/// apps so they can be rehashed with a PasswordCypher on the next successful login
+#[derive(Debug, Clone, Copy)]
+pub struct ScryptCypher {
+    params: scrypt::Params,
+}
+
+impl Default for ScryptCypher {
+    fn default() -> Self {
+        Self {
+            params: scrypt::Params::recommended(),
+        }
+    }
+}
+
+impl ScryptCypher {
+    /// `log_n` is the base 2 log of the CPU/memory cost, `r` the block size and `p` the
+    /// parallelism
+    pub fn new(log_n: u8, r: u32, p: u32) -> Result<Self> {
+        let params = scrypt::Params::new(log_n, r, p, scrypt::Params::RECOMMENDED_LEN)
+            .map_err(|e| ApplicationError::InvalidParams(e.to_string()))?;
+        Ok(Self { params })
+    }
+
+    pub fn encode(&self, raw_text: impl Into<SecretString>) -> Result<String> {
+        use scrypt::password_hash::{PasswordHasher, SaltString};
+        let salt = SaltString::generate(&mut OsRng);
+        scrypt::Scrypt
+            .hash_password_customized(
+                raw_text.into().expose_secret().as_bytes(),
+                None,
+                None,
+                self.params,
+                &salt,
+            )
+            .map(|hash| hash.to_string())
+            .map_err(|e| ApplicationError::PasswordHash(e.to_string()))
+    }
+
+    /// The cost comes from the stored string, not this cypher's params
+    pub fn try_password(&self, stored: &str, raw_text: impl Into<SecretString>) -> Result<bool> {
+        use scrypt::password_hash::{Error, PasswordHash, PasswordVerifier};
+        if detect_scheme(stored) != Some(HashScheme::Scrypt) {
+            return Err(ApplicationError::PasswordHash("not an scrypt hash".into()));
+        }
+        let hash =
+            PasswordHash::new(stored).map_err(|e| ApplicationError::PhcParse(e.to_string()))?;
+        // Like bcrypt, legacy hashes are of the raw bytes
+        match scrypt::Scrypt.verify_password(raw_text.into().expose_secret().as_bytes(), &hash) {
+            Ok(()) => Ok(true),
+            Err(Error::Password) => Ok(false),
+            Err(e) => Err(ApplicationError::PasswordHash(e.to_string())),
+        }
+    }
+}
+
+impl PasswordHasher<String> for ScryptCypher {
+    fn encode(&self, raw_text: &SecretString) -> Result<String> {
+        ScryptCypher::encode(self, raw_text.clone())
+    }
+
+    fn verify(&self, stored: &String, raw_text: &SecretString) -> Result<bool> {
+        self.try_password(stored, raw_text.clone())
+    }
+}
+
+/// Checks a bcrypt or scrypt hash, whichever `detect_scheme` says `stored` is
+pub fn verify_legacy(stored: &str, raw_text: impl Into<SecretString>) -> Result<bool> {
+    match detect_scheme(stored) {
+        Some(HashScheme::Bcrypt) => BcryptCypher::default().try_password(stored, raw_text),
+        Some(HashScheme::Scrypt) => ScryptCypher::default().try_password(stored, raw_text),
+        Some(HashScheme::Argon2) => Err(ApplicationError::PasswordHash(
+            "Argon2 hashes are verified with a PasswordCypher".into(),
+        )),
+        None => Err(ApplicationError::PasswordHash(
+            "unrecognized hash scheme".into(),
+        )),
+    }
+}
+
+// Ranges are left to Params::new, this only rejects values that aren't numbers
+fn env_u32(name: &str) -> Result<Option<u32>> {
+    match std::env::var(name) {
+        Ok(value) => value.trim().parse().map(Some).map_err(|_| {
+            ApplicationError::InvalidParams(format!("{name} must be a whole number, got '{value}'"))
+        }),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(e) => Err(ApplicationError::InvalidParams(format!("{name}: {e}"))),
+    }
+}
+
+// Hashing blocks for tens to hundreds of milliseconds, these run it on tokio's blocking
+// thread pool so async handlers don't starve the executor
+#[cfg(feature = "tokio")]
+impl PasswordCypher<'static> {
+    pub async fn encode_async(&self, raw_text: impl Into<SecretString>) -> Result<Password> {
+        let cypher = self.clone();
+        let raw_text = raw_text.into();
+        tokio::task::spawn_blocking(move || cypher.encode(raw_text))
+            .await
+            .map_err(|e| ApplicationError::PasswordHash(e.to_string()))?
+    }
+
+    pub async fn try_password_async(
+        &self,
+        password: &Password,
+        raw_text: impl Into<SecretString>,
+    ) -> Result<bool> {
+        let cypher = self.clone();
+        let password = password.clone();
+        let raw_text = raw_text.into();
+        tokio::task::spawn_blocking(move || cypher.try_password(&password, raw_text))
+            .await
+            .map_err(|e| ApplicationError::PasswordHash(e.to_string()))?
+    }
+}
+
+// Every Argon2 computation allocates its full memory cost, so a batch runs only as many
+// at once as fit in this budget
+#[cfg(feature = "rayon")]
+const BATCH_MEMORY_BUDGET_KIB: u32 = 512 * 1024;
+
+#[cfg(feature = "rayon")]
+impl PasswordCypher<'_> {
+    /// Verifies many passwords in parallel, e.g. for migration tooling. Results are in
+    /// the same order as `items`.
+    pub fn verify_batch(&self, items: &[(Password, String)]) -> Vec<Result<bool>> {
+        use rayon::prelude::*;
+
+        let verify = |(password, raw_text): &(Password, String)| {
+            self.try_password(password, raw_text.as_str())
+        };
+        let largest_m_cost = items
+            .iter()
+            .map(|(password, _)| password.params.m_cost())
+            .max()
+            .unwrap_or(1);
+        let threads = (BATCH_MEMORY_BUDGET_KIB / largest_m_cost.max(1))
+            .clamp(1, rayon::current_num_threads() as u32);
+        match rayon::ThreadPoolBuilder::new()
+            .num_threads(threads as usize)
+            .build()
+        {
+            Ok(pool) => pool.install(|| items.par_iter().map(verify).collect()),
+            // Without a pool fall back to one at a time rather than failing the batch
+            Err(_) => items.iter().map(verify).collect(),
+        }
+    }
+}
+
+/// Serializes as the PHC string so the wire format stays portable
+#[cfg(feature = "serde")]
+impl serde::Serialize for Password {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_phc_string())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Password {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let phc = String::deserialize(deserializer)?;
+        Password::from_phc_string(&phc).map_err(serde::de::Error::custom)
+    }
+}
+
+/// The Argon2 flavours, Id is the recommended default
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Argon2Variant {
+    #[default]
+    Id,
+    I,
+    D,
+}
+
+impl From<Argon2Variant> for Algorithm {
+    fn from(variant: Argon2Variant) -> Self {
+        match variant {
+            Argon2Variant::Id => Algorithm::Argon2id,
+            Argon2Variant::I => Algorithm::Argon2i,
+            Argon2Variant::D => Algorithm::Argon2d,
+        }
+    }
+}
+
+/// Builds a PasswordCypher with custom Argon2 costs, anything left unset uses the Argon2 default
+#[derive(Clone, Default)]
+pub struct PasswordCypherBuilder<'a> {
+    memory_cost: Option<u32>,
+    time_cost: Option<u32>,
+    parallelism: Option<u32>,
+    pepper: Option<&'a [u8]>,
+    variant: Argon2Variant,
+    max_password_length: Option<usize>,
+    salt_length: Option<usize>,
+    output_length: Option<usize>,
+    max_memory_cost: Option<u32>,
+}
+
+impl<'a> PasswordCypherBuilder<'a> {
+    /// Memory size in KiB
+    pub fn memory_cost(&mut self, kib: u32) -> &mut Self {
+        self.memory_cost = Some(kib);
+        self
+    }
+
+    /// Largest memory size in KiB that `build` accepts, defaults to 4 GiB
+    pub fn max_memory_cost(&mut self, kib: u32) -> &mut Self {
+        self.max_memory_cost = Some(kib);
+        self
+    }
+
+    /// Number of iterations
+    pub fn time_cost(&mut self, iterations: u32) -> &mut Self {
+        self.time_cost = Some(iterations);
+        self
+    }
+
+    /// Number of lanes
+    pub fn parallelism(&mut self, lanes: u32) -> &mut Self {
+        self.parallelism = Some(lanes);
+        self
+    }
+
+    /// Application-wide secret mixed into every hash, store it outside the database
+    /// so a leaked database alone can't be brute-forced.
+    ///
+    /// Changing or losing the pepper invalidates every password encoded with it!
+    pub fn with_pepper(&mut self, secret: &'a [u8]) -> &mut Self {
+        self.pepper = Some(secret);
+        self
+    }
+
+    pub fn variant(&mut self, variant: Argon2Variant) -> &mut Self {
+        self.variant = variant;
+        self
+    }
+
+    /// Longest raw password in bytes that will be encoded, defaults to 1024
+    pub fn max_password_length(&mut self, bytes: usize) -> &mut Self {
+        self.max_password_length = Some(bytes);
+        self
+    }
+
+    /// Salt length in bytes, 8 to 64, defaults to 16
+    pub fn salt_length(&mut self, bytes: usize) -> &mut Self {
+        self.salt_length = Some(bytes);
+        self
+    }
+
+    /// Hash output length in bytes, 16 to 64, defaults to 32
+    pub fn output_length(&mut self, bytes: usize) -> &mut Self {
+        self.output_length = Some(bytes);
+        self
+    }
+
+    pub fn build(&self) -> Result<PasswordCypher<'a>> {
+        let salt_length = self.salt_length.unwrap_or(DEFAULT_SALT_LENGTH);
+        if !SALT_LENGTHS.contains(&salt_length) {
+            return Err(ApplicationError::InvalidParams(format!(
+                "salt length must be {} to {} bytes",
+                SALT_LENGTHS.start(),
+                SALT_LENGTHS.end()
+            )));
+        }
+        let output_length = self.output_length.unwrap_or(DEFAULT_OUTPUT_LENGTH);
+        if !OUTPUT_LENGTHS.contains(&output_length) {
+            return Err(ApplicationError::InvalidParams(format!(
+                "output length must be {} to {} bytes",
+                OUTPUT_LENGTHS.start(),
+                OUTPUT_LENGTHS.end()
+            )));
+        }
+        let memory_cost = self.memory_cost.unwrap_or(Params::DEFAULT_M_COST);
+        let memory_costs = MIN_MEMORY_KIB..=self.max_memory_cost.unwrap_or(DEFAULT_MAX_MEMORY_KIB);
+        if !memory_costs.contains(&memory_cost) {
+            return Err(ApplicationError::InvalidParams(format!(
+                "memory cost must be {} to {} KiB, got {memory_cost}",
+                memory_costs.start(),
+                memory_costs.end()
+            )));
+        }
+        let algorithm = self.variant.into();
+        let version = Version::default();
+        let params = Params::new(
+            memory_cost,
+            self.time_cost.unwrap_or(Params::DEFAULT_T_COST),
+            self.parallelism.unwrap_or(Params::DEFAULT_P_COST),
+            None,
+        )
+        .map_err(|e| ApplicationError::InvalidParams(e.to_string()))?;
+        Ok(PasswordCypher {
+            argon: new_argon2(self.pepper, algorithm, version, params)?,
+            algorithm,
+            version,
+            pepper: self.pepper,
+            max_password_length: self
+                .max_password_length
+                .unwrap_or(DEFAULT_MAX_PASSWORD_LENGTH),
+            salt_length,
+            output_length,
+        })
+    }
+}
+
+impl fmt::Debug for Password {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Let's keep the password a secret
+        f.debug_struct("Password").finish()
+    }
+}
+
+/// A stored password that can only be checked, e.g. one fetched from a secrets service.
+/// Unlike Password there's no way back to the PHC string or hash bytes once it's built.
+///
+/// ```compile_fail
+/// # use secure_web_applictions_in_rust::{PasswordCypher, PasswordVerifier};
+/// let verifier = PasswordVerifier::from(PasswordCypher::default().encode("123")?);
+/// verifier.to_phc_string();
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone)]
+pub struct PasswordVerifier(Password);
+
+impl PasswordVerifier {
+    pub fn from_phc_string(s: &str) -> Result<Self> {
+        Password::from_phc_string(s).map(Self)
+    }
+
+    pub fn verify(
+        &self,
+        hasher: &dyn PasswordHasher,
+        raw_password: impl Into<SecretString>,
+    ) -> Result<bool> {
+        hasher.verify(&self.0, &raw_password.into())
+    }
+}
+
+impl From<Password> for PasswordVerifier {
+    fn from(password: Password) -> Self {
+        Self(password)
+    }
+}
+
+impl fmt::Debug for PasswordVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PasswordVerifier").finish()
+    }
+}
+
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PasswordStrength {
+    VeryWeak,
+    Weak,
+    Fair,
+    Strong,
+}
+
+// A handful of the most common passwords, these are the first guesses of any attacker
+const COMMON_PASSWORDS: [&str; 20] = [
+    "123456",
+    "password",
+    "123456789",
+    "12345678",
+    "12345",
+    "qwerty",
+    "1234567",
+    "111111",
+    "123123",
+    "abc123",
+    "password1",
+    "1234567890",
+    "iloveyou",
+    "000000",
+    "qwerty123",
+    "admin",
+    "letmein",
+    "welcome",
+    "monkey",
+    "dragon",
+];
+
+/// Scores a candidate password by length, character classes and whether it is a common
+/// password, use it to reject weak passwords before they are ever encoded
+pub fn password_strength(raw: &str) -> PasswordStrength {
+    let length = raw.chars().count();
+    if length < 6 || COMMON_PASSWORDS.contains(&raw.to_lowercase().as_str()) {
+        return PasswordStrength::VeryWeak;
+    }
+    let length_score = match length {
+        0..=7 => 0,
+        8..=11 => 1,
+        12..=15 => 2,
+        _ => 3,
+    };
+    let classes = [
+        raw.chars().any(|c| c.is_lowercase()),
+        raw.chars().any(|c| c.is_uppercase()),
+        raw.chars().any(|c| c.is_numeric()),
+        raw.chars().any(|c| !c.is_alphanumeric()),
+    ]
+    .into_iter()
+    .filter(|c| *c)
+    .count();
+    match length_score + classes - 1 {
+        0..=1 => PasswordStrength::Weak,
+        2..=3 => PasswordStrength::Fair,
+        _ => PasswordStrength::Strong,
+    }
+}
+
+/// What makes a username acceptable, by default 3 to 32 ASCII letters, digits, `_`, `-` or `.`
+#[derive(Debug, Clone, Getters, Builder)]
+#[builder(default, build_fn(error = "ApplicationError"))]
+pub struct UsernameRules {
+    min_length: usize,
+    max_length: usize,
+    // Allowed on top of ASCII letters and digits
+    allowed_symbols: Vec<char>,
+}
+
+impl Default for UsernameRules {
+    fn default() -> Self {
+        Self {
+            min_length: 3,
+            max_length: 32,
+            allowed_symbols: vec!['_', '-', '.'],
+        }
+    }
+}
+
+impl UsernameRules {
+    pub fn validate(&self, username: &str) -> Result<()> {
+        let length = username.chars().count();
+        if length < self.min_length || length > self.max_length {
+            return Err(ApplicationError::InvalidUsername(
+                UsernameViolation::Length(self.min_length, self.max_length),
+            ));
+        }
+        if let Some(c) = username
+            .chars()
+            .find(|c| !c.is_ascii_alphanumeric() && !self.allowed_symbols.contains(c))
+        {
+            return Err(ApplicationError::InvalidUsername(
+                UsernameViolation::Character(c),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The `UsernameRules` rule a username didn't meet
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum UsernameViolation {
+    #[error("must be {0} to {1} characters long")]
+    Length(usize, usize),
+    #[error("'{0}' is not allowed")]
+    Character(char),
+}
+
+impl UsernameViolation {
+    /// A full sentence for showing to an end user, walking the language's fallback chain
+    /// until a translation is found, e.g. "Username must be 3 to 32 characters long"
+    pub fn localized_message(&self, lang: Language) -> String {
+        lang.fallback_chain()
+            .iter()
+            .find_map(|lang| self.translation(lang))
+            .unwrap_or_else(|| match self {
+                Self::Length(min, max) => {
+                    format!("Username must be {min} to {max} characters long")
+                }
+                Self::Character(c) => format!("Username must not contain '{c}'"),
+            })
+    }
+
+    fn translation(&self, lang: &Language) -> Option<String> {
+        use Language::{French, German, Spanish};
+        let message = match (lang, self) {
+            (Spanish, Self::Length(min, max)) => {
+                format!("El nombre de usuario debe tener entre {min} y {max} caracteres")
+            }
+            (Spanish, Self::Character(c)) => {
+                format!("El nombre de usuario no puede contener '{c}'")
+            }
+            (French, Self::Length(min, max)) => {
+                format!("Le nom d'utilisateur doit contenir entre {min} et {max} caractères")
+            }
+            (French, Self::Character(c)) => {
+                format!("Le nom d'utilisateur ne peut pas contenir '{c}'")
+            }
+            (German, Self::Length(min, max)) => {
+                format!("Der Benutzername muss {min} bis {max} Zeichen lang sein")
+            }
+            (German, Self::Character(c)) => format!("Der Benutzername darf '{c}' nicht enthalten"),
+            _ => return None,
+        };
+        Some(message)
+    }
+}
+
+// UserBuilder's storage for the email together with the policy it must meet
+#[derive(Debug, Clone, Default)]
+struct EmailField {
+    value: Option<String>,
+    policy: Option<EmailPolicy>,
+}
+
+// UserBuilder's storage for the username together with the rules it must follow
+#[derive(Debug, Clone, Default)]
+struct UsernameField {
+    value: Option<String>,
+    rules: UsernameRules,
+}
+
+/// Where an account is in its lifecycle, only Active accounts can log in
+#[derive(
+    Debug, Display, Clone, Copy, PartialEq, Eq, Default, EnumIter, EnumString, IntoStaticStr,
+)]
+#[strum(serialize_all = "snake_case")]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum AccountStatus {
+    Active,
+    Locked,
+    Disabled,
+    // New signups until their email is confirmed
+    #[default]
+    PendingVerification,
+}
+
+impl AccountStatus {
+    /// The error to refuse a login with, if any
+    pub fn ensure_active(self) -> Result<()> {
+        match self {
+            Self::Active => Ok(()),
+            Self::Locked => Err(ApplicationError::AccountLocked),
+            Self::Disabled => Err(ApplicationError::AccountDisabled),
+            Self::PendingVerification => Err(ApplicationError::AccountPendingVerification),
+        }
+    }
+}
+
+/// Something a user may want to do, gated by `User::can`
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, EnumIter)]
+pub enum Action {
+    ViewContent,
+    PostContent,
+    ModerateContent,
+    ManageUsers,
+}
+
+#[derive(
+    Debug,
+    Display,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    EnumIter,
+    EnumString,
+    IntoStaticStr,
+)]
+#[strum(serialize_all = "snake_case")]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Role {
+    User,
+    Moderator,
+    Admin,
+}
+
+impl Role {
+    /// What the role grants, a user's abilities are the union over their roles
+    pub fn permissions(&self) -> &'static [Action] {
+        match self {
+            Self::User => &[Action::ViewContent, Action::PostContent],
+            Self::Moderator => &[
+                Action::ViewContent,
+                Action::PostContent,
+                Action::ModerateContent,
+            ],
+            Self::Admin => &[
+                Action::ViewContent,
+                Action::PostContent,
+                Action::ModerateContent,
+                Action::ManageUsers,
+            ],
+        }
+    }
+}
+
+// The password serializes as its PHC string, so no raw hash bytes end up in the output
+#[derive(Clone, Getters, Builder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[builder(build_fn(validate = "Self::validate", error = "ApplicationError"))]
+pub struct User {
+    id: i64,
+    #[builder(
+        setter(custom),
+        field(ty = "UsernameField", build = "self.build_username()?")
+    )]
+    username: String,
+    // Stored lowercased so differently cased registrations are the same account
+    #[builder(
+        setter(custom),
+        field(ty = "EmailField", build = "self.build_email()?")
+    )]
+    email: String,
+    // Lets keep the password a secret
+    #[getter(skip)]
+    password: Password,
+    language: Language,
+    // Set once an EmailVerificationToken is consumed
+    #[builder(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
+    verified: bool,
+    #[builder(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
+    status: AccountStatus,
+    #[builder(default = "default_roles()")]
+    #[cfg_attr(feature = "serde", serde(default = "default_roles"))]
+    roles: BTreeSet<Role>,
+    #[builder(default = "SystemTime::now()")]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default = "SystemTime::now", with = "unix_time")
+    )]
+    created_at: SystemTime,
+    #[builder(default)]
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default,
+            skip_serializing_if = "Option::is_none",
+            with = "unix_time::option"
+        )
+    )]
+    last_login: Option<SystemTime>,
+    // Present once the user has enrolled an authenticator app
+    #[getter(skip)]
+    #[builder(default)]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    totp: Option<TotpSecret>,
+    // Previous passwords, so change_password can refuse going back to one
+    #[getter(skip)]
+    #[builder(default)]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "PasswordHistory::is_empty")
+    )]
+    password_history: PasswordHistory,
+    // A bcrypt or scrypt hash imported from another app, `password` is a placeholder until
+    // `auth::authenticate` verifies it and swaps in an Argon2 one
+    #[getter(skip)]
+    #[builder(setter(custom), default)]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    legacy_password: Option<String>,
+}
+
+/// Seconds are plenty for account timestamps and keep storage formats simple
+pub fn to_unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+pub fn from_unix_seconds(seconds: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+// Timestamps serialize as unix seconds rather than serde's default secs/nanos struct
+#[cfg(feature = "serde")]
+mod unix_time {
+    use std::time::SystemTime;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(super::to_unix_seconds(*time))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        Ok(super::from_unix_seconds(u64::deserialize(deserializer)?))
+    }
+
+    pub mod option {
+        use std::time::SystemTime;
+
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            time: &Option<SystemTime>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match time {
+                Some(time) => super::serialize(time, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<SystemTime>, D::Error> {
+            Ok(Option::<u64>::deserialize(deserializer)?.map(super::super::from_unix_seconds))
+        }
+    }
+}
+
+// Everyone starts out as a plain user
+fn default_roles() -> BTreeSet<Role> {
+    BTreeSet::from([Role::User])
+}
+
+impl PartialEq for User {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for User {}
+
+// Ordered by id only, the same as equality, so sorted users come out in id order
+impl PartialOrd for User {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for User {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.id.cmp(&other.id)
+    }
+}
+
+// Keeps the first character of the address so it's recognizable, the fixed length mask
+// doesn't reveal how long the rest is
+fn mask_email(email: &str) -> String {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            let first = local.chars().next().map(String::from).unwrap_or_default();
+            format!("{first}******@{domain}")
+        }
+        None => "******".into(),
+    }
+}
+
+// The email is PII, mask it here too and leave out the password entirely
+impl fmt::Debug for User {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("User")
+            .field("id", &self.id)
+            .field("username", &self.username)
+            .field("email", &mask_email(&self.email))
+            .field("language", &self.language)
+            .finish_non_exhaustive()
+    }
+}
+
+// Safe for logs, the email is masked and the password never shown
+impl fmt::Display for User {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "User id {}, username {}, email {}, language {}",
+            self.id,
+            self.username,
+            mask_email(&self.email),
+            self.language
+        )
+    }
+}
+
+// Hashes only the id to stay consistent with equality
+impl Hash for User {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+/// A username as a set or map key. Users are equal by id, so this is what to index by when
+/// usernames have to be unique. Case and Unicode lookalike forms are folded together.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct UsernameKey(String);
+
+impl UsernameKey {
+    pub fn new(username: &str) -> Self {
+        Self(normalize(username).to_lowercase())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&User> for UsernameKey {
+    fn from(user: &User) -> Self {
+        Self::new(&user.username)
+    }
+}
+
+/// Checks the common local@domain.tld shape, it's not a full RFC 5322 parser.
+/// The only real proof an address works is sending mail to it.
+fn validate_email(email: &str) -> Result<()> {
+    let err = |violation| Err(ApplicationError::InvalidEmail(violation));
+    if email.chars().any(char::is_whitespace) {
+        return err(EmailViolation::Whitespace);
+    }
+    let Some((local, domain)) = email.split_once('@') else {
+        return err(EmailViolation::MissingAt);
+    };
+    if local.is_empty() {
+        return err(EmailViolation::EmptyLocalPart);
+    }
+    if domain.is_empty() {
+        return err(EmailViolation::EmptyDomain);
+    }
+    if domain.contains('@') {
+        return err(EmailViolation::MultipleAt);
+    }
+    if !domain.contains('.') || domain.split('.').any(str::is_empty) {
+        return err(EmailViolation::MalformedDomain);
+    }
+    Ok(())
+}
+
+/// Why an email was rejected, by its shape or by an `EmailPolicy`
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum EmailViolation {
+    #[error("must not contain whitespace")]
+    Whitespace,
+    #[error("missing '@'")]
+    MissingAt,
+    #[error("empty local part")]
+    EmptyLocalPart,
+    #[error("empty domain")]
+    EmptyDomain,
+    #[error("more than one '@'")]
+    MultipleAt,
+    #[error("malformed domain")]
+    MalformedDomain,
+    #[error("domain '{0}' is not allowed")]
+    DomainNotAllowed(String),
+    #[error("domain '{0}' is blocked")]
+    DomainBlocked(String),
+}
+
+impl EmailViolation {
+    /// A full sentence for showing to an end user, walking the language's fallback chain
+    /// until a translation is found, e.g. "Email is missing '@'"
+    pub fn localized_message(&self, lang: Language) -> String {
+        lang.fallback_chain()
+            .iter()
+            .find_map(|lang| self.translation(lang))
+            .unwrap_or_else(|| match self {
+                Self::Whitespace => "Email must not contain whitespace".into(),
+                Self::MissingAt => "Email is missing '@'".into(),
+                Self::EmptyLocalPart => "Email is missing the part before '@'".into(),
+                Self::EmptyDomain => "Email is missing its domain".into(),
+                Self::MultipleAt => "Email has more than one '@'".into(),
+                Self::MalformedDomain => "Email domain is not valid".into(),
+                Self::DomainNotAllowed(d) => format!("Email domain '{d}' is not allowed"),
+                Self::DomainBlocked(d) => format!("Email domain '{d}' is blocked"),
+            })
+    }
+
+    fn translation(&self, lang: &Language) -> Option<String> {
+        use Language::{French, German, Spanish};
+        let message = match (lang, self) {
+            (Spanish, Self::Whitespace) => {
+                "El correo electrónico no puede contener espacios".into()
+            }
+            (Spanish, Self::MissingAt) => "Al correo electrónico le falta '@'".into(),
+            (Spanish, Self::EmptyLocalPart) => {
+                "Al correo electrónico le falta la parte antes de '@'".into()
+            }
+            (Spanish, Self::EmptyDomain) => "Al correo electrónico le falta el dominio".into(),
+            (Spanish, Self::MultipleAt) => "El correo electrónico tiene más de una '@'".into(),
+            (Spanish, Self::MalformedDomain) => {
+                "El dominio del correo electrónico no es válido".into()
+            }
+            (Spanish, Self::DomainNotAllowed(d)) => format!("El dominio '{d}' no está permitido"),
+            (Spanish, Self::DomainBlocked(d)) => format!("El dominio '{d}' está bloqueado"),
+            (French, Self::Whitespace) => "L'e-mail ne peut pas contenir d'espaces".into(),
+            (French, Self::MissingAt) => "Il manque '@' dans l'e-mail".into(),
+            (French, Self::EmptyLocalPart) => "Il manque la partie avant '@' dans l'e-mail".into(),
+            (French, Self::EmptyDomain) => "Il manque le domaine dans l'e-mail".into(),
+            (French, Self::MultipleAt) => "L'e-mail contient plus d'un '@'".into(),
+            (French, Self::MalformedDomain) => "Le domaine de l'e-mail est mal formé".into(),
+            (French, Self::DomainNotAllowed(d)) => format!("Le domaine '{d}' n'est pas autorisé"),
+            (French, Self::DomainBlocked(d)) => format!("Le domaine '{d}' est bloqué"),
+            (German, Self::Whitespace) => "Die E-Mail darf keine Leerzeichen enthalten".into(),
+            (German, Self::MissingAt) => "Der E-Mail fehlt das '@'".into(),
+            (German, Self::EmptyLocalPart) => "Der E-Mail fehlt der Teil vor dem '@'".into(),
+            (German, Self::EmptyDomain) => "Der E-Mail fehlt die Domain".into(),
+            (German, Self::MultipleAt) => "Die E-Mail enthält mehr als ein '@'".into(),
+            (German, Self::MalformedDomain) => "Die Domain der E-Mail ist ungültig".into(),
+            (German, Self::DomainNotAllowed(d)) => format!("Die Domain '{d}' ist nicht erlaubt"),
+            (German, Self::DomainBlocked(d)) => format!("Die Domain '{d}' ist gesperrt"),
+            _ => return None,
+        };
+        Some(message)
+    }
+}
+
+/// Which email domains may sign up, e.g. only a company's, or no disposable providers.
+/// Domains match case-insensitively, when `allowed_domains` is set nothing else may sign up.
+#[derive(Debug, Clone, Default, Getters, Builder)]
+#[builder(default, build_fn(error = "ApplicationError"))]
+pub struct EmailPolicy {
+    allowed_domains: Option<HashSet<String>>,
+    blocked_domains: Option<HashSet<String>>,
+}
+
+impl EmailPolicy {
+    pub fn validate(&self, email: &str) -> Result<()> {
+        let domain = email.rsplit_once('@').map(|(_, d)| d).unwrap_or_default();
+        let listed =
+            |domains: &HashSet<String>| domains.iter().any(|d| d.eq_ignore_ascii_case(domain));
+        if self
+            .allowed_domains
+            .as_ref()
+            .is_some_and(|allowed| !listed(allowed))
+        {
+            return Err(ApplicationError::InvalidEmail(
+                EmailViolation::DomainNotAllowed(domain.into()),
+            ));
+        }
+        if self.blocked_domains.as_ref().is_some_and(listed) {
+            return Err(ApplicationError::InvalidEmail(
+                EmailViolation::DomainBlocked(domain.into()),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl UserBuilder {
+    /// Sets a bcrypt or scrypt hash from another app in place of the password, the user is
+    /// migrated to Argon2 the first time they log in
+    pub fn legacy_password(&mut self, stored: String) -> Result<&mut Self> {
+        if !matches!(
+            detect_scheme(&stored),
+            Some(HashScheme::Bcrypt | HashScheme::Scrypt)
+        ) {
+            return Err(ApplicationError::PasswordHash(
+                "not a bcrypt or scrypt hash".into(),
+            ));
+        }
+        self.password = Some(Password::redacted());
+        self.legacy_password = Some(Some(stored));
+        Ok(self)
+    }
+
+    pub fn username(&mut self, username: String) -> &mut Self {
+        self.username.value = Some(username);
+        self
+    }
+
+    /// Rules the username is validated against, defaults to `UsernameRules::default()`
+    pub fn username_rules(&mut self, rules: UsernameRules) -> &mut Self {
+        self.username.rules = rules;
+        self
+    }
+
+    fn build_username(&self) -> Result<String> {
+        let username = self
+            .username
+            .value
+            .clone()
+            .ok_or(UninitializedFieldError::new("username"))?;
+        self.username.rules.validate(&username)?;
+        Ok(username)
+    }
+
+    pub fn email(&mut self, email: String) -> &mut Self {
+        self.email.value = Some(email.to_lowercase());
+        self
+    }
+
+    /// Domains the email must meet, by default any well formed address is accepted
+    pub fn email_policy(&mut self, policy: EmailPolicy) -> &mut Self {
+        self.email.policy = Some(policy);
+        self
+    }
+
+    fn build_email(&self) -> Result<String> {
+        Ok(self
+            .email
+            .value
+            .clone()
+            .ok_or(UninitializedFieldError::new("email"))?)
+    }
+
+    fn check_email(&self, email: &str) -> Result<()> {
+        validate_email(email)?;
+        match &self.email.policy {
+            Some(policy) => policy.validate(email),
+            None => Ok(()),
+        }
+    }
+
+    /// Encodes the raw password with `cypher` and sets it, for registration code
+    pub fn raw_password(
+        &mut self,
+        cypher: &PasswordCypher,
+        raw_password: impl Into<SecretString>,
+    ) -> Result<&mut Self> {
+        self.password = Some(cypher.encode(raw_password)?);
+        Ok(self)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if let Some(email) = &self.email.value {
+            self.check_email(email)?;
+        }
+        Ok(())
+    }
+
+    /// Same as `build`, but the email, username and password are all checked first and
+    /// every problem is returned together in `ApplicationError::Validation`, so a form
+    /// can show them all at once
+    pub fn build_validated(&self) -> Result<User> {
+        let mut errors = Vec::new();
+        match &self.email.value {
+            Some(email) => errors.extend(self.check_email(email).err()),
+            None => errors.push(UninitializedFieldError::new("email").into()),
+        }
+        errors.extend(self.build_username().err());
+        if self.password.is_none() {
+            errors.push(UninitializedFieldError::new("password").into());
+        }
+        if !errors.is_empty() {
+            return Err(ApplicationError::Validation(errors));
+        }
+        self.build()
+    }
+}
+
+impl User {
+    /// Checks the raw password against the stored one. It only borrows the user, so a
+    /// user looked up from a repository or a cache needn't be cloned to be verified.
+    pub fn verify_password(
+        &self,
+        hasher: &dyn PasswordHasher,
+        raw_password: impl Into<SecretString>,
+    ) -> Result<bool> {
+        hasher.verify(&self.password, &raw_password.into())
+    }
+
+    /// A copy that's safe to hand to logging or analytics code, the password hash is
+    /// replaced with a placeholder that never verifies. Use `Clone` inside the crate.
+    pub fn clone_for_audit(&self) -> User {
+        User {
+            password: Password::redacted(),
+            totp: None,
+            password_history: PasswordHistory::new(0),
+            legacy_password: None,
+            ..self.clone()
+        }
+    }
+
+    /// False for copies made by `clone_for_audit`
+    pub fn has_real_password(&self) -> bool {
+        !self.password.is_redacted() || self.legacy_password.is_some()
+    }
+
+    /// True until a user imported with `UserBuilder::legacy_password` logs in
+    pub fn has_legacy_password(&self) -> bool {
+        self.legacy_password.is_some()
+    }
+
+    /// Same as `verify_password`
+    pub fn try_password_with(
+        &self,
+        hasher: &dyn PasswordHasher,
+        raw_password: impl Into<SecretString>,
+    ) -> Result<bool> {
+        self.verify_password(hasher, raw_password)
+    }
+
+    /// The stored language, unless an `Accept-Language` header doesn't accept it. Then the
+    /// first language of its fallback chain the header does accept, and failing that the
+    /// header's own pick. Without a usable header the stored language always wins.
+    pub fn preferred_language(&self, accept_header: Option<&str>) -> Language {
+        let Some(header) = accept_header else {
+            return self.language.clone();
+        };
+        let accepted: Vec<_> = Language::accepted(header).map(|(_, l)| l).collect();
+        self.language
+            .fallback_chain()
+            .into_iter()
+            .find(|language| accepted.contains(language))
+            .or_else(|| Language::negotiate(header))
+            .unwrap_or_else(|| self.language.clone())
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.status == AccountStatus::Active
+    }
+
+    /// A color for the default avatar, always the same for the same username.
+    /// FNV-1a rather than std's hasher, whose output may change between Rust releases.
+    pub fn identicon_color(&self) -> (u8, u8, u8) {
+        let hash = self
+            .username
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+            });
+        let [r, g, b, ..] = hash.to_be_bytes();
+        (r, g, b)
+    }
+
+    pub fn set_status(&mut self, status: AccountStatus) {
+        self.status = status;
+    }
+
+    /// Stamps a successful login
+    pub fn record_login(&mut self) {
+        self.last_login = Some(SystemTime::now());
+    }
+
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role)
+    }
+
+    /// True when any of the user's roles grants the action
+    pub fn can(&self, action: Action) -> bool {
+        self.roles
+            .iter()
+            .any(|role| role.permissions().contains(&action))
+    }
+
+    pub fn add_role(&mut self, role: Role) {
+        self.roles.insert(role);
+    }
+
+    pub fn remove_role(&mut self, role: Role) {
+        self.roles.remove(&role);
+    }
+
+    pub fn has_totp(&self) -> bool {
+        self.totp.is_some()
+    }
+
+    /// Enrolls, or with `None` removes, the user's authenticator app
+    pub fn set_totp(&mut self, totp: Option<TotpSecret>) {
+        self.totp = totp;
+    }
+
+    /// False when the user hasn't enrolled TOTP at all
+    pub fn verify_totp(&self, code: &str) -> bool {
+        self.totp.as_ref().is_some_and(|totp| totp.verify(code))
+    }
+
+    /// Replaces the password after verifying the current one, the new password can't be
+    /// the current one or one in the user's password history
+    pub fn change_password(
+        &mut self,
+        cypher: &PasswordCypher,
+        old_password: impl Into<SecretString>,
+        new_password: impl Into<SecretString>,
+    ) -> Result<()> {
+        if !cypher.try_password(&self.password, old_password)? {
+            return Err(ApplicationError::IncorrectPassword);
+        }
+        let new_password = new_password.into();
+        if cypher.try_password(&self.password, new_password.clone())?
+            || self
+                .password_history
+                .was_recently_used(cypher, new_password.clone())?
+        {
+            return Err(ApplicationError::PasswordReused);
+        }
+        let previous = std::mem::replace(&mut self.password, cypher.encode(new_password)?);
+        self.password_history.push(previous);
+        Ok(())
+    }
+
+    /// Same as `change_password`, recording a successful change in `audit`
+    pub fn change_password_audited(
+        &mut self,
+        cypher: &PasswordCypher,
+        audit: &dyn AuditSink,
+        old_password: impl Into<SecretString>,
+        new_password: impl Into<SecretString>,
+    ) -> Result<()> {
+        self.change_password(cypher, old_password, new_password)?;
+        audit.record(AuditEvent::PasswordChanged {
+            user_id: self.id,
+            at: std::time::SystemTime::now(),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_application_error_display() {
+        for (error, message) in [
+            (ApplicationError::LanguageNotFound, "Language Not Found"),
+            (
+                ApplicationError::PasswordHash("oops".into()),
+                "Password Hashing Failed: oops",
+            ),
+            (
+                ApplicationError::PhcParse("missing hash".into()),
+                "Invalid PHC String: missing hash",
+            ),
+            (
+                ApplicationError::InvalidParams("time cost is too small".into()),
+                "Invalid Argon2 Parameters: time cost is too small",
+            ),
+            (
+                ApplicationError::InvalidPassword(PasswordViolation::Empty),
+                "Invalid Password: must not be empty",
+            ),
+            (
+                ApplicationError::InvalidEmail(EmailViolation::MissingAt),
+                "Invalid Email: missing '@'",
+            ),
+            (
+                ApplicationError::InvalidUsername(UsernameViolation::Character('!')),
+                "Invalid Username: '!' is not allowed",
+            ),
+            (ApplicationError::IncorrectPassword, "Incorrect Password"),
+            (
+                UninitializedFieldError::new("id").into(),
+                "Field not initialized: id",
+            ),
+            (
+                argon2::Error::TimeTooSmall.into(),
+                "Password Hashing Failed: time cost is too small",
+            ),
+            (
+                ApplicationError::InsufficientMemory(19456),
+                "Insufficient Memory: could not allocate 19456 KiB for hashing",
+            ),
+        ] {
+            assert_eq!(error.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_language_all() {
+        let all = Language::all();
+        assert_eq!(all.len(), 10);
+        assert_eq!(all.first(), Some(&Language::English));
+        assert!(all
+            .iter()
+            .all(|l| !l.to_string().is_empty() && !l.native_name().is_empty()));
+    }
+
+    #[test]
+    fn test_language_is_rtl() {
+        assert!(Language::Arabic.is_rtl());
+        assert!(!Language::English.is_rtl());
+        assert!(!Language::Japanese.is_rtl());
+        assert_eq!(Language::iter().filter(Language::is_rtl).count(), 1);
+    }
+
+    #[test]
+    fn test_language_display_name_in() {
+        assert_eq!(
+            Language::Spanish.display_name_in(Language::English),
+            "Spanish"
+        );
+        assert_eq!(
+            Language::Spanish.display_name_in(Language::Spanish),
+            "Español"
+        );
+        assert_eq!(
+            Language::German.display_name_in(Language::French),
+            "Allemand"
+        );
+        assert_eq!(
+            Language::Japanese.display_name_in(Language::German),
+            "Japanisch"
+        );
+        // No Korean translations yet
+        assert_eq!(
+            Language::Russian.display_name_in(Language::Korean),
+            "Russian"
+        );
+    }
+
+    #[test]
+    fn test_language_from_iso639_2() {
+        assert_eq!(Language::from_iso639_2("eng").ok(), Some(Language::English));
+        assert_eq!(Language::from_iso639_2("fra").ok(), Some(Language::French));
+        assert_eq!(Language::from_iso639_2("fre").ok(), Some(Language::French));
+        assert_eq!(Language::from_iso639_2("ZHO").ok(), Some(Language::Chinese));
+        assert_eq!(Language::from_iso639_2("chi").ok(), Some(Language::Chinese));
+        assert!(matches!(
+            Language::from_iso639_2("xyz"),
+            Err(ApplicationError::LanguageNotFound)
+        ));
+        assert!(Language::from_iso639_2("en").is_err());
+        for language in Language::iter() {
+            assert!(language.get_str("iso639_2").is_some());
+        }
+    }
+
+    #[test]
+    fn test_language_direction() {
+        assert_eq!(Language::Arabic.direction(), TextDirection::Rtl);
+        assert_eq!(Language::Arabic.direction().to_string(), "rtl");
+        assert_eq!(Language::English.direction().to_string(), "ltr");
+        for language in Language::iter().filter(|l| *l != Language::Arabic) {
+            assert_eq!(language.direction(), TextDirection::Ltr);
+        }
+    }
+
+    #[test]
+    fn test_language_plural_category() {
+        assert_eq!(Language::English.plural_category(1), PluralCategory::One);
+        assert_eq!(Language::English.plural_category(2), PluralCategory::Other);
+        assert_eq!(Language::English.plural_category(0), PluralCategory::Other);
+        assert_eq!(Language::Russian.plural_category(1), PluralCategory::One);
+        assert_eq!(Language::Russian.plural_category(2), PluralCategory::Few);
+        assert_eq!(Language::Russian.plural_category(5), PluralCategory::Many);
+        assert_eq!(Language::Russian.plural_category(11), PluralCategory::Many);
+        assert_eq!(Language::Russian.plural_category(21), PluralCategory::One);
+        assert_eq!(Language::Russian.plural_category(112), PluralCategory::Many);
+        assert_eq!(Language::French.plural_category(0), PluralCategory::One);
+        assert_eq!(Language::Spanish.plural_category(0), PluralCategory::Other);
+        assert_eq!(
+            Language::Spanish.plural_category(2_000_000),
+            PluralCategory::Many
+        );
+        assert_eq!(Language::Arabic.plural_category(0), PluralCategory::Zero);
+        assert_eq!(Language::Arabic.plural_category(2), PluralCategory::Two);
+        assert_eq!(Language::Arabic.plural_category(103), PluralCategory::Few);
+        assert_eq!(Language::Arabic.plural_category(11), PluralCategory::Many);
+        assert_eq!(Language::Arabic.plural_category(100), PluralCategory::Other);
+        for n in [0, 1, 2, 5, 1_000_000] {
+            assert_eq!(Language::Japanese.plural_category(n), PluralCategory::Other);
+        }
+    }
+
+    #[test]
+    fn test_language_native_name() {
+        assert_eq!(Language::English.native_name(), "English");
+        assert_eq!(Language::Spanish.native_name(), "Español");
+        assert_eq!(Language::Chinese.native_name(), "中文");
+        assert_eq!(Language::Arabic.native_name(), "العربية");
+        assert_eq!(Language::Russian.native_name(), "Русский");
+    }
+
+    #[test]
+    fn test_language_negotiate() {
+        assert_eq!(
+            Language::negotiate("fr-CH, fr;q=0.9, en;q=0.8"),
+            Some(Language::French)
+        );
+        assert_eq!(
+            Language::negotiate("en;q=0.5, de;q=0.7, ja;q=0.6"),
+            Some(Language::German)
+        );
+        assert_eq!(Language::negotiate("pt-BR"), Some(Language::Portuguese));
+        assert_eq!(
+            Language::negotiate("xx, es;q=banana, ko;q=0.2, ;;, *;q=0.9"),
+            Some(Language::Korean)
+        );
+        assert_eq!(
+            Language::negotiate("ru;q=0, zh;q=0.1"),
+            Some(Language::Chinese)
+        );
+        assert_eq!(Language::negotiate("nl-NL, sv;q=0.8, *;q=0.1"), None);
+        assert_eq!(Language::negotiate(""), None);
+    }
+
+    #[test]
+    fn test_language_fallback_chain() {
+        assert_eq!(
+            Language::Portuguese.fallback_chain(),
+            vec![Language::Portuguese, Language::Spanish, Language::English]
+        );
+        assert_eq!(
+            Language::Korean.fallback_chain(),
+            vec![Language::Korean, Language::English]
+        );
+        assert_eq!(Language::English.fallback_chain(), vec![Language::English]);
+        for language in Language::iter() {
+            let chain = language.fallback_chain();
+            assert_eq!(chain.first(), Some(&language));
+            assert_eq!(chain.last(), Some(&Language::English));
+        }
+    }
+
+    #[test]
+    fn test_language_bcp47() {
+        assert_eq!(Language::Chinese.bcp47(), "zh-Hans");
+        assert_eq!(Language::English.bcp47(), "en");
+        for language in Language::iter() {
+            assert!(language
+                .get_str("lang")
+                .is_some_and(|l| language.bcp47().starts_with(l)));
+        }
+    }
+
+    #[test]
+    fn test_language_from_str() {
+        assert_eq!("en".parse::<Language>().ok(), Some(Language::English));
+        assert_eq!("EN".parse::<Language>().ok(), Some(Language::English));
+        assert_eq!("En".parse::<Language>().ok(), Some(Language::English));
+        assert_eq!("en-GB".parse::<Language>().ok(), Some(Language::English));
+        assert_eq!(
+            "zh-Hans-CN".parse::<Language>().ok(),
+            Some(Language::Chinese)
+        );
+        assert!(matches!(
+            "zz".parse::<Language>(),
+            Err(ApplicationError::LanguageNotFound)
+        ));
+        assert!(matches!(
+            "".parse::<Language>(),
+            Err(ApplicationError::LanguageNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_localized_message() {
+        let error = ApplicationError::LanguageNotFound;
+        assert_eq!(
+            error.localized_message(Language::English),
+            "Language Not Found"
+        );
+        assert_eq!(
+            error.localized_message(Language::Spanish),
+            "Idioma no encontrado"
+        );
+        // Portuguese has no translation of its own and falls back to Spanish
+        assert_eq!(
+            error.localized_message(Language::Portuguese),
+            "Idioma no encontrado"
+        );
+        assert_eq!(
+            error.localized_message(Language::Japanese),
+            "Language Not Found"
+        );
+        assert_eq!(
+            ApplicationError::InvalidEmail(EmailViolation::MissingAt)
+                .localized_message(Language::German),
+            "Der E-Mail fehlt das '@'"
+        );
+        assert_eq!(
+            ApplicationError::InvalidUsername(UsernameViolation::Character('!'))
+                .localized_message(Language::French),
+            "Le nom d'utilisateur ne peut pas contenir '!'"
+        );
+        assert_eq!(
+            ApplicationError::DuplicateUser(UniqueField::Username)
+                .localized_message(Language::Spanish),
+            "El nombre de usuario ya está en uso"
+        );
+        assert_eq!(
+            ApplicationError::DuplicateUser(UniqueField::Email).to_string(),
+            "Duplicate User: email already taken"
+        );
+        assert_eq!(
+            ApplicationError::InvalidPassword(PasswordViolation::TooLong(1024))
+                .localized_message(Language::German),
+            "Das Passwort darf höchstens 1024 Bytes lang sein"
+        );
+        // Internal errors stay in English
+        assert_eq!(
+            ApplicationError::Database("locked".into()).localized_message(Language::French),
+            "Database Error: locked"
+        );
+    }
+
+    #[test]
+    fn test_locale_from_str() -> Result<()> {
+        let locale: Locale = "pt-BR".parse()?;
+        assert_eq!(locale.language(), &Language::Portuguese);
+        assert_eq!(locale.region().map(|r| r.to_string()), Some("BR".into()));
+        assert_eq!(locale.to_string(), "pt-BR");
+        let locale: Locale = "en".parse()?;
+        assert_eq!(locale, Locale::new(Language::English, None));
+        assert_eq!(locale.to_string(), "en");
+        assert_eq!("en_us".parse::<Locale>()?.to_string(), "en-US");
+        assert_eq!("zh-Hans-CN".parse::<Locale>()?.to_string(), "zh-CN");
+        for invalid in ["en-USA", "en-1X", "en-US-x"] {
+            assert!(matches!(
+                invalid.parse::<Locale>(),
+                Err(ApplicationError::InvalidRegion(_))
+            ));
+        }
+        assert!(matches!(
+            "xx-US".parse::<Locale>(),
+            Err(ApplicationError::LanguageNotFound)
+        ));
+        Ok(())
+    }
+
+    #[cfg(feature = "language-tags")]
+    #[test]
+    fn test_language_tags() -> Result<(), Box<dyn std::error::Error>> {
+        use language_tags::LanguageTag;
+        let tag = LanguageTag::parse("pt-BR")?;
+        assert_eq!(Language::try_from(&tag)?, Language::Portuguese);
+        let tag = LanguageTag::parse("zh-Hant-TW")?;
+        assert_eq!(Language::try_from(&tag)?, Language::Chinese);
+        assert!(matches!(
+            Language::try_from(&LanguageTag::parse("nl-NL")?),
+            Err(ApplicationError::LanguageNotFound)
+        ));
+        assert_eq!(LanguageTag::from(Language::Chinese).as_str(), "zh-Hans");
+        assert_eq!(LanguageTag::from(Language::German).as_str(), "de");
+        for language in Language::iter() {
+            assert_eq!(
+                Language::try_from(&LanguageTag::from(language.clone()))?,
+                language
+            );
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_language_serde() -> Result<(), serde_json::Error> {
+        for language in Language::iter() {
+            let json = serde_json::to_string(&language)?;
+            assert_eq!(
+                json,
+                format!("\"{}\"", language.get_str("lang").unwrap_or_default())
+            );
+            assert_eq!(serde_json::from_str::<Language>(&json)?, language);
+        }
+        assert_eq!(serde_json::to_string(&Language::Korean)?, "\"ko\"");
+        assert!(serde_json::from_str::<Language>("\"zz\"").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_cypher_encode() -> Result<()> {
+        let cypher = PasswordCypher::default();
+        let password = cypher.encode("123")?;
+        assert!(!password.salt.iter().all(|b| *b == 0u8));
+        assert!(!password.hash.iter().all(|b| *b == 0u8));
+        assert_ne!(password.hash, "123".as_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_cypher_encode_with_rng() -> Result<()> {
+        use rand::{rngs::StdRng, SeedableRng};
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let encode = |seed| cypher.encode_with_rng("123", &mut StdRng::seed_from_u64(seed));
+        let password = encode(7)?;
+        assert_eq!(password.to_phc_string(), encode(7)?.to_phc_string());
+        assert_ne!(password.to_phc_string(), encode(8)?.to_phc_string());
+        assert!(cypher.try_password(&password, "123")?);
+        Ok(())
+    }
+
+    // Inputs stay at 16 characters so even NFKC's longest expansions fit the length limit,
+    // cases are few since each one is a full Argon2 hash
+    proptest::proptest! {
+        #![proptest_config(proptest::prelude::ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn test_password_cypher_encode_roundtrip_prop(raw in "\\PC{1,16}") {
+            let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+            let password = cypher.encode(raw.as_str())?;
+            proptest::prop_assert!(cypher.try_password(&password, raw)?);
+        }
+
+        #[test]
+        fn test_password_cypher_encode_rejects_other_prop(
+            raw in "\\PC{1,16}",
+            other in "\\PC{1,16}",
+        ) {
+            // Lookalike forms that normalize the same are meant to match
+            proptest::prop_assume!(*normalize(&raw) != *normalize(&other));
+            let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+            let password = cypher.encode(raw)?;
+            proptest::prop_assert!(!cypher.try_password(&password, other)?);
+        }
+    }
+
+    #[test]
+    fn test_password_verifier() -> Result<()> {
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let password = cypher.encode("123")?;
+        let verifier = PasswordVerifier::from_phc_string(&password.to_phc_string())?;
+        assert!(verifier.verify(&cypher, "123")?);
+        assert!(!verifier.verify(&cypher, "1234")?);
+        assert!(PasswordVerifier::from(password).verify(&cypher, "123")?);
+        // There's nothing to print, or otherwise read back, but that it's a verifier
+        assert_eq!(format!("{verifier:?}"), "PasswordVerifier");
+        assert!(PasswordVerifier::from_phc_string("not a phc string").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_cypher_insufficient_memory() -> Result<()> {
+        // Roughly 4 TiB, more than the allocator hands out even with heuristic overcommit
+        let cypher = PasswordCypherBuilder::default()
+            .max_memory_cost(u32::MAX)
+            .memory_cost(u32::MAX)
+            .build()?;
+        assert!(matches!(
+            cypher.encode("123"),
+            Err(ApplicationError::InsufficientMemory(_))
+        ));
+        assert!(matches!(
+            cypher.dummy_verify("123"),
+            Err(ApplicationError::InsufficientMemory(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hashing_error_memory_too_much() {
+        assert!(matches!(
+            hashing_error(argon2::Error::MemoryTooMuch, 1024),
+            ApplicationError::InsufficientMemory(1024)
+        ));
+        assert!(matches!(
+            hashing_error(argon2::Error::PwdTooLong, 1024),
+            ApplicationError::PasswordHash(_)
+        ));
+    }
+
+    #[test]
+    fn test_password_cypher_encode_length_limits() -> Result<()> {
+        let cypher = PasswordCypher::default();
+        assert!(matches!(
+            cypher.encode(""),
+            Err(ApplicationError::InvalidPassword(_))
+        ));
+        assert!(matches!(
+            cypher.encode("a".repeat(1025)),
+            Err(ApplicationError::InvalidPassword(_))
+        ));
+        cypher.encode("a".repeat(1024))?;
+        let cypher = PasswordCypherBuilder::default()
+            .memory_cost(1024)
+            .max_password_length(8)
+            .build()?;
+        assert!(matches!(
+            cypher.encode("123456789"),
+            Err(ApplicationError::InvalidPassword(_))
+        ));
+        let password = cypher.encode("12345678")?;
+        assert!(cypher.try_password(&password, "12345678")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_cypher_encode_reader() -> Result<()> {
+        use std::io::Cursor;
+        let cypher = PasswordCypherBuilder::default()
+            .memory_cost(1024)
+            .max_password_length(8)
+            .build()?;
+        let password = cypher.encode_reader(&mut Cursor::new("hunter2\n"))?;
+        assert!(cypher.try_password(&password, "hunter2")?);
+        let password = cypher.encode_reader(&mut Cursor::new("12345678\r\n"))?;
+        assert!(cypher.try_password(&password, "12345678")?);
+        let mut huge = Cursor::new(vec![b'a'; 1024 * 1024]);
+        assert!(matches!(
+            cypher.encode_reader(&mut huge),
+            Err(ApplicationError::InvalidPassword(_))
+        ));
+        // Only a little past the limit was read
+        assert_eq!(huge.position(), 10);
+        assert!(matches!(
+            cypher.encode_reader(&mut Cursor::new(b"\xff\xfe".to_vec())),
+            Err(ApplicationError::InvalidPassword(_))
+        ));
+        assert!(matches!(
+            cypher.encode_reader(&mut Cursor::new("\n")),
+            Err(ApplicationError::InvalidPassword(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_cypher_reuses_argon2() -> Result<()> {
+        let cypher = PasswordCypherBuilder::default()
+            .memory_cost(2048)
+            .time_cost(3)
+            .build()?;
+        let password = cypher.encode("123")?;
+        for _ in 0..3 {
+            assert!(cypher.try_password(&password, "123")?);
+            assert!(matches!(
+                cypher.argon_for(&password)?,
+                Cow::Borrowed(argon) if std::ptr::eq(argon, &cypher.argon)
+            ));
+        }
+        let other = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let legacy = other.encode("123")?;
+        assert!(matches!(cypher.argon_for(&legacy)?, Cow::Owned(_)));
+        assert!(cypher.try_password(&legacy, "123")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_cypher_generate_temporary() -> Result<()> {
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        assert!(!TEMPORARY_PASSWORD_ALPHABET
+            .iter()
+            .any(|b| b"0Oo1lI".contains(b)));
+        let (raw, password) = cypher.generate_temporary(16)?;
+        assert_eq!(raw.chars().count(), 16);
+        assert!(raw
+            .bytes()
+            .all(|b| TEMPORARY_PASSWORD_ALPHABET.contains(&b)));
+        assert!(cypher.try_password(&password, raw.clone())?);
+        let (other, _) = cypher.generate_temporary(16)?;
+        assert_ne!(raw, other);
+        assert!(matches!(
+            cypher.generate_temporary(MIN_TEMPORARY_PASSWORD_LENGTH - 1),
+            Err(ApplicationError::InvalidPassword(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_cypher_try_password() -> Result<()> {
+        let cypher = PasswordCypher::default();
+        let password = cypher.encode("123")?;
+        assert!(cypher.try_password(&password, "123")?);
+        assert!(!cypher.try_password(&password, "1234")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_secret_string() -> Result<()> {
+        let secret = SecretString::from("hunter2");
+        assert_eq!(format!("{secret:?}"), "[REDACTED]");
+        assert_eq!(format!("{:?}", Some(secret.clone())), "Some([REDACTED])");
+        assert_eq!(secret.expose_secret(), "hunter2");
+        let cypher = PasswordCypher::default();
+        let password = cypher.encode(secret.clone())?;
+        assert!(cypher.try_password(&password, secret)?);
+        assert!(cypher.try_password(&password, String::from("hunter2"))?);
+        assert!(cypher.try_password(&password, "hunter2")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_zeroize() -> Result<()> {
+        let cypher = PasswordCypher::default();
+        let mut password = cypher.encode("123")?;
+        assert!(cypher.try_password(&password.clone(), "123")?);
+        password.zeroize();
+        assert!(password.salt.iter().all(|b| *b == 0u8));
+        assert!(password.hash.iter().all(|b| *b == 0u8));
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_cypher_unicode_normalization() -> Result<()> {
+        let composed = "caf\u{e9}";
+        let decomposed = "cafe\u{301}";
+        assert_ne!(composed.as_bytes(), decomposed.as_bytes());
+        let cypher = PasswordCypher::default();
+        assert!(cypher.try_password(&cypher.encode(composed)?, decomposed)?);
+        assert!(cypher.try_password(&cypher.encode(decomposed)?, composed)?);
+        assert!(!cypher.try_password(&cypher.encode(composed)?, "cafe")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_credential_version() -> Result<()> {
+        let params = Params::new(1024, 2, 1, None)?;
+        let v1 = PasswordCypher::new(Algorithm::Argon2i, Version::V0x10, params.clone());
+        assert_eq!(v1.encode("123")?.version(), CredentialVersion::V1);
+        let v2 = PasswordCypher::new(Algorithm::Argon2id, Version::V0x13, params);
+        assert_eq!(v2.encode("123")?.version(), CredentialVersion::CURRENT);
+        assert!(CredentialVersion::V1 < CredentialVersion::V2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_params() -> Result<()> {
+        let cypher = PasswordCypher::new(
+            Algorithm::Argon2i,
+            Version::V0x13,
+            Params::new(2048, 3, 2, None)?,
+        );
+        let summary = cypher.encode("123")?.params();
+        assert_eq!(
+            (
+                *summary.memory_kib(),
+                *summary.iterations(),
+                *summary.parallelism(),
+                *summary.variant()
+            ),
+            (2048, 3, 2, Algorithm::Argon2i)
+        );
+        assert_eq!(summary.to_string(), "argon2i m=2048,t=3,p=2");
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_cypher_custom_lengths() -> Result<()> {
+        let cypher = PasswordCypherBuilder::default()
+            .memory_cost(1024)
+            .salt_length(32)
+            .output_length(64)
+            .build()?;
+        let password = cypher.encode("123")?;
+        assert_eq!((password.salt.len(), password.hash.len()), (32, 64));
+        assert!(cypher.try_password(&password, "123")?);
+        assert!(!cypher.try_password(&password, "1234")?);
+        // The lengths survive the PHC string
+        let parsed = Password::from_phc_string(&password.to_phc_string())?;
+        assert_eq!((parsed.salt.len(), parsed.hash.len()), (32, 64));
+        assert!(cypher.try_password(&parsed, "123")?);
+        // A cypher with the default lengths still verifies it, but wants a rehash
+        let default_lengths = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        assert!(default_lengths.try_password(&parsed, "123")?);
+        assert!(default_lengths.needs_rehash(&parsed));
+        assert!(!cypher.needs_rehash(&parsed));
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_cypher_builder_rejects_lengths() {
+        for (salt, output) in [(4, 32), (128, 32), (16, 8), (16, 128)] {
+            assert!(matches!(
+                PasswordCypherBuilder::default()
+                    .salt_length(salt)
+                    .output_length(output)
+                    .build(),
+                Err(ApplicationError::InvalidParams(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_password_cypher_try_password_one_byte_difference() -> Result<()> {
+        // The comparison is constant-time, but it must still reject a hash
+        // that differs from the stored one by a single byte
+        let cypher = PasswordCypher::default();
+        let mut password = cypher.encode("123")?;
+        password.hash[31] ^= 1;
+        assert!(!cypher.try_password(&password, "123")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_cypher_try_password_custom_argon() -> Result<()> {
+        let params = Params::new(8 * 1024, 3, 1, None)?;
+        let cypher = PasswordCypher::new(Algorithm::Argon2id, Version::V0x13, params);
+        let password = cypher.encode("123")?;
+        assert!(cypher.try_password(&password, "123")?);
+        assert!(!cypher.try_password(&password, "1234")?);
+        assert!(!cypher.needs_rehash(&password));
+        assert!(PasswordCypher::default().needs_rehash(&password));
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_to_phc_string() -> Result<()> {
+        let cypher = PasswordCypher::default();
+        let password = cypher.encode("123")?;
+        let phc = password.to_phc_string();
+        assert!(phc.starts_with("$argon2id$v=19$m=19456,t=2,p=1$"));
+        let parts: Vec<&str> = phc.split('$').collect();
+        assert_eq!(parts.len(), 6);
+        assert_eq!(parts[4], Base64Unpadded::encode_string(&password.salt));
+        assert_eq!(parts[5], Base64Unpadded::encode_string(&password.hash));
+        assert!(!parts[4].ends_with('=') && !parts[5].ends_with('='));
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_from_phc_string() -> Result<()> {
+        let cypher = PasswordCypher::default();
+        let phc = cypher.encode("123")?.to_phc_string();
+        let password = Password::from_phc_string(&phc)?;
+        assert_eq!(password.to_phc_string(), phc);
+        assert!(cypher.try_password(&password, "123")?);
+        assert!(!cypher.try_password(&password, "1234")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_from_phc_string_malformed() -> Result<()> {
+        let phc = PasswordCypher::default().encode("123")?.to_phc_string();
+        let salt = phc.split('$').nth(4).unwrap_or_default();
+        for malformed in [
+            "",
+            "argon2id",
+            &phc.replace("$argon2id$", "$bcrypt$"),
+            &phc.replace("$v=19$", "$v=42$"),
+            &phc.replace("$v=19$", "$19$"),
+            &phc.replace("m=19456", "m=lots"),
+            &phc.replace("t=2,", ""),
+            &phc.replace(salt, "not*base64"),
+            &phc.replace(salt, "c2hvcnQ"),
+            &format!("{phc}$extra"),
+        ] {
+            assert!(
+                matches!(
+                    Password::from_phc_string(malformed),
+                    Err(ApplicationError::PhcParse(_))
+                ),
+                "{malformed} should not parse"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_cypher_builder() -> Result<()> {
+        let cypher = PasswordCypherBuilder::default()
+            .memory_cost(4096)
+            .time_cost(3)
+            .parallelism(2)
+            .build()?;
+        let password = cypher.encode("123")?;
+        assert!(password.to_phc_string().contains("$m=4096,t=3,p=2$"));
+        assert!(cypher.try_password(&password, "123")?);
+        assert!(!cypher.try_password(&password, "1234")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_cypher_builder_invalid_params() {
+        assert!(matches!(
+            PasswordCypherBuilder::default().time_cost(0).build(),
+            Err(ApplicationError::InvalidParams(_))
+        ));
+        assert!(matches!(
+            PasswordCypherBuilder::default().memory_cost(1).build(),
+            Err(ApplicationError::InvalidParams(_))
+        ));
+        assert!(matches!(
+            PasswordCypherBuilder::default().parallelism(0).build(),
+            Err(ApplicationError::InvalidParams(_))
+        ));
+    }
+
+    #[test]
+    fn test_password_cypher_builder_memory_bounds() {
+        let below = PasswordCypherBuilder::default().memory_cost(4).build();
+        assert_eq!(
+            below.map(|_| ()).map_err(|e| e.to_string()),
+            Err("Invalid Argon2 Parameters: memory cost must be 8 to 4194304 KiB, got 4".into())
+        );
+        assert!(matches!(
+            PasswordCypherBuilder::default()
+                .memory_cost(u32::MAX)
+                .build(),
+            Err(ApplicationError::InvalidParams(_))
+        ));
+        let above = PasswordCypherBuilder::default()
+            .memory_cost(4096)
+            .max_memory_cost(2048)
+            .build();
+        assert_eq!(
+            above.map(|_| ()).map_err(|e| e.to_string()),
+            Err("Invalid Argon2 Parameters: memory cost must be 8 to 2048 KiB, got 4096".into())
+        );
+    }
+
+    #[test]
+    fn test_password_cypher_builder_higher_time_cost_is_slower() -> Result<()> {
+        let time_encode = |cypher: &PasswordCypher| -> Result<_> {
+            let start = Instant::now();
+            let password = cypher.encode("123")?;
+            let elapsed = start.elapsed();
+            assert!(cypher.try_password(&password, "123")?);
+            Ok(elapsed)
+        };
+        let cheap = PasswordCypherBuilder::default()
+            .memory_cost(1024)
+            .time_cost(1)
+            .build()?;
+        let costly = PasswordCypherBuilder::default()
+            .memory_cost(1024)
+            .time_cost(16)
+            .build()?;
+        assert!(time_encode(&costly)? > time_encode(&cheap)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_cypher_verify_and_upgrade() -> Result<()> {
+        let cheap = PasswordCypherBuilder::default()
+            .memory_cost(1024)
+            .time_cost(1)
+            .build()?;
+        let current = PasswordCypherBuilder::default().memory_cost(2048).build()?;
+        let old = cheap.encode("123")?;
+        let VerifyOutcome::AcceptedRehashed(upgraded) = current.verify_and_upgrade(&old, "123")?
+        else {
+            return Err(ApplicationError::PasswordHash("expected a rehash".into()));
+        };
+        assert!(!current.needs_rehash(&upgraded));
+        assert!(matches!(
+            current.verify_and_upgrade(&upgraded, "123")?,
+            VerifyOutcome::Accepted
+        ));
+        assert!(matches!(
+            current.verify_and_upgrade(&upgraded, "1234")?,
+            VerifyOutcome::Rejected
+        ));
+        assert!(matches!(
+            current.verify_and_upgrade(&old, "1234")?,
+            VerifyOutcome::Rejected
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_cypher_needs_rehash() -> Result<()> {
+        let cheap = PasswordCypherBuilder::default()
+            .memory_cost(1024)
+            .time_cost(1)
+            .build()?;
+        let costly = PasswordCypherBuilder::default()
+            .memory_cost(2048)
+            .time_cost(3)
+            .build()?;
+        let password = cheap.encode("123")?;
+        assert!(!cheap.needs_rehash(&password));
+        assert!(costly.needs_rehash(&password));
+        assert!(costly.try_password(&password, "123")?);
+        assert!(!costly.try_password(&password, "1234")?);
+        let password = costly.encode("123")?;
+        assert!(!costly.needs_rehash(&password));
+        assert!(!costly.needs_rehash(&Password::from_phc_string(&password.to_phc_string())?));
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_cypher_with_pepper() -> Result<()> {
+        let peppered = PasswordCypherBuilder::default()
+            .memory_cost(1024)
+            .with_pepper(b"super secret pepper")
+            .build()?;
+        let other_pepper = PasswordCypherBuilder::default()
+            .memory_cost(1024)
+            .with_pepper(b"another pepper")
+            .build()?;
+        let unpeppered = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let password = peppered.encode("123")?;
+        assert!(peppered.try_password(&password, "123")?);
+        assert!(!unpeppered.try_password(&password, "123")?);
+        assert!(!other_pepper.try_password(&password, "123")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_cypher_associated_data() -> Result<()> {
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let user_id = 42_i64.to_be_bytes();
+        let password = cypher.encode_with_ad("123", &user_id)?;
+        assert!(cypher.try_password_with_ad(&password, "123", &user_id)?);
+        assert!(!cypher.try_password_with_ad(&password, "1234", &user_id)?);
+        assert!(!cypher.try_password_with_ad(&password, "123", &43_i64.to_be_bytes())?);
+        assert!(!cypher.try_password(&password, "123")?);
+        assert!(!cypher.try_password_with_ad(&cypher.encode("123")?, "123", &user_id)?);
+        assert!(matches!(
+            cypher.encode_with_ad("123", &[0; 33]),
+            Err(ApplicationError::InvalidParams(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_cypher_rehash_with_new_pepper() -> Result<()> {
+        let (old_pepper, new_pepper) = (b"old pepper".as_slice(), b"new pepper".as_slice());
+        let old = PasswordCypherBuilder::default()
+            .memory_cost(1024)
+            .with_pepper(old_pepper)
+            .build()?;
+        let new = PasswordCypherBuilder::default()
+            .memory_cost(1024)
+            .with_pepper(new_pepper)
+            .build()?;
+        let password = old.encode("123")?;
+        let rehashed = old.rehash_with_new_pepper(old_pepper, new_pepper, &password, "123")?;
+        assert!(new.try_password(&rehashed, "123")?);
+        assert!(!old.try_password(&rehashed, "123")?);
+        assert!(matches!(
+            old.rehash_with_new_pepper(old_pepper, new_pepper, &password, "1234"),
+            Err(ApplicationError::IncorrectPassword)
+        ));
+        assert!(matches!(
+            old.rehash_with_new_pepper(b"wrong pepper", new_pepper, &password, "123"),
+            Err(ApplicationError::IncorrectPassword)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_cypher_dummy_verify() -> Result<()> {
+        let cypher = PasswordCypher::default();
+        for raw in ["", "123", "correct horse battery staple", "ñ́ 密码 🔑"] {
+            cypher.dummy_verify(raw)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_cypher_calibrate() -> Result<()> {
+        let cypher = PasswordCypher::calibrate(Duration::ZERO)?;
+        assert_eq!(cypher.params().m_cost(), Params::DEFAULT_M_COST);
+        assert_eq!(cypher.params().t_cost(), 1);
+        let cypher = PasswordCypher::calibrate(Duration::from_millis(50))?;
+        assert!(cypher.params().m_cost() >= Params::DEFAULT_M_COST);
+        assert!(cypher.params().m_cost() <= CALIBRATION_MAX_M_COST);
+        assert!(cypher.params().t_cost() >= 1);
+        assert!(cypher.params().t_cost() <= CALIBRATION_MAX_T_COST);
+        let password = cypher.encode("123")?;
+        assert!(cypher.try_password(&password, "123")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_cypher_variants() -> Result<()> {
+        for (variant, id) in [
+            (Argon2Variant::Id, "argon2id"),
+            (Argon2Variant::I, "argon2i"),
+            (Argon2Variant::D, "argon2d"),
+        ] {
+            let cypher = PasswordCypherBuilder::default()
+                .memory_cost(1024)
+                .variant(variant)
+                .build()?;
+            let phc = cypher.encode("123")?.to_phc_string();
+            assert!(phc.starts_with(&format!("${id}$")));
+            let password = Password::from_phc_string(&phc)?;
+            assert!(cypher.try_password(&password, "123")?);
+            assert!(!cypher.try_password(&password, "1234")?);
+            // Other cyphers pick the algorithm recorded in the password
+            assert!(PasswordCypher::default().try_password(&password, "123")?);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_password_serde() -> Result<(), Box<dyn std::error::Error>> {
+        let cypher = PasswordCypher::default();
+        let password = cypher.encode("123")?;
+        let json = serde_json::to_string(&password)?;
+        assert_eq!(json, format!("\"{}\"", password.to_phc_string()));
+        let password: Password = serde_json::from_str(&json)?;
+        assert!(cypher.try_password(&password, "123")?);
+        assert!(serde_json::from_str::<Password>("\"$argon2id$nope\"").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_strength() {
+        for (raw, strength) in [
+            ("", PasswordStrength::VeryWeak),
+            ("abc", PasswordStrength::VeryWeak),
+            ("password", PasswordStrength::VeryWeak),
+            ("PassWord", PasswordStrength::VeryWeak),
+            ("123456789", PasswordStrength::VeryWeak),
+            ("abcdef", PasswordStrength::Weak),
+            ("abcdefgh", PasswordStrength::Weak),
+            ("abcdefgh1", PasswordStrength::Fair),
+            ("correcthorsebattery", PasswordStrength::Fair),
+            ("Abcdefgh1!", PasswordStrength::Strong),
+            ("Correct horse battery staple", PasswordStrength::Strong),
+        ] {
+            assert_eq!(password_strength(raw), strength, "{raw}");
+        }
+    }
+
+    // Environment variables are process wide, so every case lives in this one test
+    #[test]
+    fn test_password_cypher_from_env() -> Result<()> {
+        let vars = [
+            "ARGON2_MEMORY_KIB",
+            "ARGON2_ITERATIONS",
+            "ARGON2_PARALLELISM",
+        ];
+        let set = |values: [Option<&str>; 3]| {
+            for (name, value) in vars.iter().zip(values) {
+                match value {
+                    Some(value) => std::env::set_var(name, value),
+                    None => std::env::remove_var(name),
+                }
+            }
+        };
+        set([None, None, None]);
+        let cypher = PasswordCypher::from_env()?;
+        assert_eq!(cypher.params().m_cost(), Params::DEFAULT_M_COST);
+        set([Some("2048"), Some("3"), Some("2")]);
+        let cypher = PasswordCypher::from_env()?;
+        assert_eq!(cypher.params().m_cost(), 2048);
+        assert_eq!(cypher.params().t_cost(), 3);
+        assert_eq!(cypher.params().p_cost(), 2);
+        let password = cypher.encode("123")?;
+        assert!(cypher.try_password(&password, "123")?);
+        set([Some("lots"), None, None]);
+        assert!(matches!(
+            PasswordCypher::from_env(),
+            Err(ApplicationError::InvalidParams(_))
+        ));
+        set([None, Some("0"), None]);
+        assert!(matches!(
+            PasswordCypher::from_env(),
+            Err(ApplicationError::InvalidParams(_))
+        ));
+        set([None, None, None]);
+        Ok(())
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_password_cypher_verify_batch() -> Result<()> {
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let items: Vec<(Password, String)> = [("1", "1"), ("2", "2"), ("3", "x"), ("4", "4")]
+            .into_iter()
+            .map(|(stored, raw)| Ok((cypher.encode(stored)?, raw.to_owned())))
+            .collect::<Result<_>>()?;
+        let results = cypher
+            .verify_batch(&items)
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(results, vec![true, true, false, true]);
+        assert!(cypher.verify_batch(&[]).is_empty());
+        Ok(())
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_password_cypher_async() -> Result<()> {
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let password = cypher.encode_async("123").await?;
+        assert!(cypher.try_password_async(&password, "123").await?);
+        assert!(!cypher.try_password_async(&password, "1234").await?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_try_password_with() -> Result<(), Box<dyn std::error::Error>> {
+        let cypher = PasswordCypher::default();
+        let user = UserBuilder::default()
+            .id(1)
+            .username("someuser100".into())
+            .email("someuser100@protomail.com".into())
+            .password(cypher.encode("123")?)
+            .language("en".parse()?)
+            .build()?;
+        assert!(user.try_password_with(&cypher, "123")?);
+        assert!(!user.try_password_with(&cypher, "1234")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_preferred_language() -> Result<(), Box<dyn std::error::Error>> {
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let user = |language: &str| -> Result<User, Box<dyn std::error::Error>> {
+            Ok(UserBuilder::default()
+                .id(1)
+                .username("someuser100".into())
+                .email("someuser100@protomail.com".into())
+                .password(cypher.encode("123")?)
+                .language(language.parse()?)
+                .build()?)
+        };
+        let french = user("fr")?;
+        // Stored language honored
+        assert_eq!(french.preferred_language(None), Language::French);
+        assert_eq!(
+            french.preferred_language(Some("de, fr;q=0.3")),
+            Language::French
+        );
+        assert_eq!(french.preferred_language(Some("nl, sv")), Language::French);
+        // Header override
+        assert_eq!(
+            french.preferred_language(Some("ja, ko;q=0.8, fr;q=0")),
+            Language::Japanese
+        );
+        // Fallback chain
+        assert_eq!(
+            user("pt")?.preferred_language(Some("de, es;q=0.5")),
+            Language::Spanish
+        );
+        assert_eq!(
+            french.preferred_language(Some("ja, en;q=0.5")),
+            Language::English
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_username_key() -> Result<(), Box<dyn std::error::Error>> {
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let user_a = build_user(&cypher, 1, "UserA")?;
+        let usera = build_user(&cypher, 2, "usera")?;
+        assert_ne!(user_a, usera);
+        assert_eq!(UsernameKey::from(&user_a), UsernameKey::from(&usera));
+        let taken = HashSet::from([UsernameKey::from(&user_a)]);
+        assert!(taken.contains(&UsernameKey::from(&usera)));
+        assert!(!taken.contains(&UsernameKey::from(&build_user(&cypher, 3, "userb")?)));
+        // Fullwidth letters fold to their ASCII forms
+        assert_eq!(UsernameKey::new("ＵｓｅｒＡ").as_str(), "usera");
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_clone_for_audit() -> Result<(), Box<dyn std::error::Error>> {
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let mut user = build_user(&cypher, 1, "someuser100")?;
+        user.set_totp(Some(TotpSecret::generate()));
+        let audit = user.clone_for_audit();
+        assert!(user.has_real_password());
+        assert!(!audit.has_real_password());
+        assert!(!audit.has_totp());
+        assert_eq!(audit, user);
+        assert_eq!(audit.username(), user.username());
+        for raw in ["123", "", "1234"] {
+            assert!(!audit.verify_password(&cypher, raw)?);
+        }
+        assert!(!audit.verify_password(&PasswordCypher::default(), "123")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_verify_password_borrows() -> Result<(), Box<dyn std::error::Error>> {
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let users = [build_user(&cypher, 1, "someuser100")?];
+        // Verifying through a shared reference, the user is never cloned
+        let user: &User = &users[0];
+        assert!(user.verify_password(&cypher, "123")?);
+        assert!(!user.verify_password(&cypher, "1234")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect_scheme() -> Result<()> {
+        let phc = PasswordCypher::default().encode("123")?.to_phc_string();
+        assert_eq!(detect_scheme(&phc), Some(HashScheme::Argon2));
+        assert_eq!(
+            detect_scheme("$2b$04$EGdrhbKUv8Oc9vGiXX0HQOxSg445d458Muh7DAHskb6QbtCvdxcie"),
+            Some(HashScheme::Bcrypt)
+        );
+        assert_eq!(
+            detect_scheme("$2a$04$EGdrhbKUv8Oc9vGiXX0HQOxSg445d458Muh7DAHskb6QbtCvdxcie"),
+            Some(HashScheme::Bcrypt)
+        );
+        assert_eq!(
+            detect_scheme("$scrypt$ln=16,r=8,p=1$aM15713r3Xsvxbi31lqr1Q$nFNh2CVHVjNldFVKDHDlm4CbdRSCdEBsjjJxD+iCs5E"),
+            Some(HashScheme::Scrypt)
+        );
+        assert_eq!(detect_scheme("5f4dcc3b5aa765d61d8327deb882cf99"), None);
+        assert_eq!(detect_scheme("$1$saltsalt$hash"), None);
+        assert_eq!(detect_scheme(""), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bcrypt_cypher_try_password() -> Result<()> {
+        // Generated with python's bcrypt library
+        let stored = "$2b$04$EGdrhbKUv8Oc9vGiXX0HQOxSg445d458Muh7DAHskb6QbtCvdxcie";
+        let cypher = BcryptCypher::default();
+        assert!(cypher.try_password(stored, "correctbatteryhorsestapler")?);
+        assert!(!cypher.try_password(stored, "correctbatteryhorsestaple")?);
+        let phc = PasswordCypher::default().encode("123")?.to_phc_string();
+        assert!(cypher.try_password(&phc, "123").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bcrypt_cypher_hasher() -> Result<()> {
+        let hasher: &dyn PasswordHasher<String> = &BcryptCypher::new(4);
+        let stored = hasher.encode(&"123".into())?;
+        assert_eq!(detect_scheme(&stored), Some(HashScheme::Bcrypt));
+        assert!(hasher.verify(&stored, &"123".into())?);
+        assert!(!hasher.verify(&stored, &"1234".into())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scrypt_cypher() -> Result<()> {
+        // Cheap settings, the recommended ones take a while per hash
+        let cypher = ScryptCypher::new(4, 8, 1)?;
+        let stored = cypher.encode("123")?;
+        assert_eq!(detect_scheme(&stored), Some(HashScheme::Scrypt));
+        assert!(cypher.try_password(&stored, "123")?);
+        assert!(!cypher.try_password(&stored, "1234")?);
+        // Stored strings carry their own cost
+        assert!(ScryptCypher::default().try_password(&stored, "123")?);
+        assert!(verify_legacy(&stored, "123")?);
+        let phc = PasswordCypher::default().encode("123")?.to_phc_string();
+        assert!(cypher.try_password(&phc, "123").is_err());
+        assert!(verify_legacy(&phc, "123").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_builder_validates_email() -> Result<(), Box<dyn std::error::Error>> {
+        let cypher = PasswordCypher::default();
+        let mut builder = UserBuilder::default();
+        builder
+            .id(1)
+            .username("someuser100".into())
+            .password(cypher.encode("123")?)
+            .language("en".parse()?);
+        for email in [
+            "someuser100@protomail.com",
+            "some.user+tag@mail.example.co.uk",
+        ] {
+            assert_eq!(builder.email(email.into()).build()?.email(), email);
+        }
+        for email in [
+            "",
+            "not an email",
+            "someuser100protomail.com",
+            "@protomail.com",
+            "someuser100@",
+            "someuser100@protomail",
+            "someuser100@protomail.",
+            "someuser100@.com",
+            "some@user100@protomail.com",
+        ] {
+            assert!(
+                matches!(
+                    builder.email(email.into()).build(),
+                    Err(ApplicationError::InvalidEmail(_))
+                ),
+                "{email} should be invalid"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_builder_build_validated() -> Result<(), Box<dyn std::error::Error>> {
+        let error = UserBuilder::default()
+            .id(1)
+            .username("a".into())
+            .email("not-an-email".into())
+            .language("en".parse()?)
+            .build_validated()
+            .err();
+        assert!(matches!(
+            error.as_ref(),
+            Some(ApplicationError::Validation(errors)) if matches!(
+                errors.as_slice(),
+                [
+                    ApplicationError::InvalidEmail(_),
+                    ApplicationError::InvalidUsername(_),
+                    ApplicationError::UninitializedField(_)
+                ]
+            )
+        ));
+        assert!(error.is_some_and(|e| e
+            .to_string()
+            .starts_with("Validation Failed: Invalid Email")));
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let user = UserBuilder::default()
+            .id(1)
+            .username("someuser100".into())
+            .email("someuser100@protomail.com".into())
+            .raw_password(&cypher, "123")?
+            .language("en".parse()?)
+            .build_validated()?;
+        assert_eq!(user.id(), &1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_builder_lowercases_email() -> Result<(), Box<dyn std::error::Error>> {
+        let cypher = PasswordCypher::default();
+        let user = UserBuilder::default()
+            .id(1)
+            .username("someuser100".into())
+            .email("SomeUser100@ProtoMail.com".into())
+            .password(cypher.encode("123")?)
+            .language("en".parse()?)
+            .build()?;
+        assert_eq!(user.email(), "someuser100@protomail.com");
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_builder_email_policy() -> Result<(), Box<dyn std::error::Error>> {
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let build = |email: &str, policy: &EmailPolicy| {
+            UserBuilder::default()
+                .id(1)
+                .username("someuser100".into())
+                .email(email.into())
+                .email_policy(policy.clone())
+                .password(cypher.encode("123")?)
+                .language(Language::English)
+                .build()
+        };
+        let corporate = EmailPolicyBuilder::default()
+            .allowed_domains(Some(HashSet::from(["example.com".into()])))
+            .build()?;
+        build("someuser100@Example.COM", &corporate)?;
+        assert!(matches!(
+            build("someuser100@protomail.com", &corporate),
+            Err(ApplicationError::InvalidEmail(_))
+        ));
+        let no_disposable = EmailPolicyBuilder::default()
+            .blocked_domains(Some(HashSet::from(["Mailinator.com".into()])))
+            .build()?;
+        assert!(matches!(
+            build("someuser100@mailinator.com", &no_disposable),
+            Err(ApplicationError::InvalidEmail(_))
+        ));
+        build("someuser100@protomail.com", &no_disposable)?;
+        build("someuser100@protomail.com", &EmailPolicy::default())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_builder_validates_username() -> Result<(), Box<dyn std::error::Error>> {
+        let cypher = PasswordCypher::default();
+        let mut builder = UserBuilder::default();
+        builder
+            .id(1)
+            .email("someuser100@protomail.com".into())
+            .password(cypher.encode("123")?)
+            .language("en".parse()?);
+        for username in ["someuser100", "some_user.100-x", "abc"] {
+            assert_eq!(
+                builder.username(username.into()).build()?.username(),
+                username
+            );
+        }
+        for username in ["", "ab", "a b c", "someuser!", "ünïcode", &"a".repeat(33)] {
+            assert!(
+                matches!(
+                    builder.username(username.into()).build(),
+                    Err(ApplicationError::InvalidUsername(_))
+                ),
+                "{username} should be invalid"
+            );
+        }
+        builder.username_rules(
+            UsernameRulesBuilder::default()
+                .min_length(1)
+                .allowed_symbols(vec![' '])
+                .build()?,
+        );
+        assert_eq!(builder.username("a b".into()).build()?.username(), "a b");
+        assert!(matches!(
+            builder.username("a.b".into()).build(),
+            Err(ApplicationError::InvalidUsername(_))
+        ));
+        assert!(matches!(
+            UserBuilder::default().build(),
+            Err(ApplicationError::UninitializedField(_))
+        ));
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_user_serde() -> Result<(), Box<dyn std::error::Error>> {
+        let cypher = PasswordCypher::default();
+        let password = cypher.encode("123")?;
+        let user = UserBuilder::default()
+            .id(1)
+            .username("someuser100".into())
+            .email("someuser100@protomail.com".into())
+            .password(password.clone())
+            .language("en".parse()?)
+            .created_at(from_unix_seconds(1700000000))
+            .build()?;
+        let json = serde_json::to_string(&user)?;
+        assert_eq!(
+            json,
+            format!(
+                "{{\"id\":1,\"username\":\"someuser100\",\"email\":\"someuser100@protomail.com\",\
+                 \"password\":\"{}\",\"language\":\"en\",\"verified\":false,\
+                 \"status\":\"pending_verification\",\"roles\":[\"user\"],\"created_at\":1700000000}}",
+                password.to_phc_string()
+            )
+        );
+        let user: User = serde_json::from_str(&json)?;
+        assert_eq!(user.id(), &1);
+        assert_eq!(user.username(), "someuser100");
+        assert_eq!(user.email(), "someuser100@protomail.com");
+        assert_eq!(user.language(), &Language::English);
+        assert_eq!(user.created_at(), &from_unix_seconds(1700000000));
+        assert_eq!(user.last_login(), &None);
+        assert!(user.try_password_with(&cypher, "123")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_totp() -> Result<(), Box<dyn std::error::Error>> {
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let mut user = build_user(&cypher, 1, "someuser100")?;
+        assert!(!user.has_totp());
+        assert!(!user.verify_totp("000000"));
+        let totp = TotpSecret::generate();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+        let code = format!("{:06}", totp.code_at(now));
+        user.set_totp(Some(totp));
+        assert!(user.verify_totp(&code));
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_builder_raw_password() -> Result<(), Box<dyn std::error::Error>> {
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let user = UserBuilder::default()
+            .id(1)
+            .username("someuser100".into())
+            .email("someuser100@protomail.com".into())
+            .raw_password(&cypher, "123")?
+            .language("en".parse()?)
+            .build()?;
+        assert!(user.try_password_with(&cypher, "123")?);
+        assert!(!user.try_password_with(&cypher, "1234")?);
+        assert!(matches!(
+            UserBuilder::default().raw_password(&cypher, ""),
+            Err(ApplicationError::InvalidPassword(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_roles() -> Result<(), Box<dyn std::error::Error>> {
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let mut user = build_user(&cypher, 1, "someuser100")?;
+        assert_eq!(user.roles(), &BTreeSet::from([Role::User]));
+        assert!(user.can(Action::PostContent));
+        assert!(!user.can(Action::ManageUsers));
+        let admin = UserBuilder::default()
+            .id(2)
+            .username("someuser200".into())
+            .email("someuser200@protomail.com".into())
+            .raw_password(&cypher, "123")?
+            .language("en".parse()?)
+            .roles(BTreeSet::from([Role::Admin]))
+            .build()?;
+        assert!(admin.has_role(Role::Admin));
+        assert!(admin.can(Action::ManageUsers));
+        user.add_role(Role::Moderator);
+        assert!(user.can(Action::ModerateContent));
+        user.remove_role(Role::Moderator);
+        assert!(!user.can(Action::ModerateContent));
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_record_login() -> Result<(), Box<dyn std::error::Error>> {
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let before = SystemTime::now();
+        let mut user = build_user(&cypher, 1, "someuser100")?;
+        assert!(*user.created_at() >= before);
+        assert_eq!(user.last_login(), &None);
+        user.record_login();
+        assert!(user.last_login().is_some_and(|at| at >= *user.created_at()));
+        // Timestamps don't affect equality
+        let mut other = user.clone();
+        other.record_login();
+        assert_eq!(user, other);
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_identicon_color() -> Result<(), Box<dyn std::error::Error>> {
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let user = build_user(&cypher, 1, "someuser100")?;
+        let same = build_user(&cypher, 2, "someuser100")?;
+        assert_eq!(user.identicon_color(), same.identicon_color());
+        // Pinned so colors don't shift between releases
+        assert_eq!(user.identicon_color(), (225, 5, 208));
+        let colors = (0..20)
+            .map(|id| Ok(build_user(&cypher, id, &format!("someuser{id}"))?.identicon_color()))
+            .collect::<Result<HashSet<_>, Box<dyn std::error::Error>>>()?;
+        assert!(colors.len() > 15);
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_sort_by_id() -> Result<(), Box<dyn std::error::Error>> {
+        use rand::seq::SliceRandom;
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let mut users = (1..=5)
+            .map(|id| build_user(&cypher, id, &format!("someuser{id}00")))
+            .collect::<Result<Vec<_>, _>>()?;
+        users.shuffle(&mut rand::thread_rng());
+        users.sort();
+        let ids = users.iter().map(|user| *user.id()).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2, 3, 4, 5]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_change_password() -> Result<(), Box<dyn std::error::Error>> {
+        let cypher = PasswordCypher::default();
+        let mut user = UserBuilder::default()
+            .id(1)
+            .username("someuser100".into())
+            .email("someuser100@protomail.com".into())
+            .password(cypher.encode("123")?)
+            .language("en".parse()?)
+            .build()?;
+        assert!(matches!(
+            user.change_password(&cypher, "1234", "456"),
+            Err(ApplicationError::IncorrectPassword)
+        ));
+        assert!(user.try_password_with(&cypher, "123")?);
+        user.change_password(&cypher, "123", "456")?;
+        assert!(!user.try_password_with(&cypher, "123")?);
+        assert!(user.try_password_with(&cypher, "456")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_change_password_audited() -> Result<(), Box<dyn std::error::Error>> {
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let mut user = build_user(&cypher, 1, "someuser100")?;
+        let audit = audit::InMemoryAuditSink::default();
+        assert!(user
+            .change_password_audited(&cypher, &audit, "1234", "456")
+            .is_err());
+        assert!(audit.events().is_empty());
+        user.change_password_audited(&cypher, &audit, "123", "456")?;
+        assert!(user.try_password_with(&cypher, "456")?);
+        assert!(matches!(
+            audit.events().as_slice(),
+            [AuditEvent::PasswordChanged { user_id: 1, .. }]
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_change_password_rejects_reuse() -> Result<(), Box<dyn std::error::Error>> {
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let mut user = build_user(&cypher, 1, "someuser100")?;
+        assert!(matches!(
+            user.change_password(&cypher, "123", "123"),
+            Err(ApplicationError::PasswordReused)
+        ));
+        user.change_password(&cypher, "123", "456")?;
+        assert!(matches!(
+            user.change_password(&cypher, "456", "123"),
+            Err(ApplicationError::PasswordReused)
+        ));
+        user.change_password(&cypher, "456", "789")?;
+        assert!(user.try_password_with(&cypher, "789")?);
+        Ok(())
+    }
+
+    fn build_user(
+        cypher: &PasswordCypher,
+        id: i64,
+        username: &str,
+    ) -> Result<User, Box<dyn std::error::Error>> {
+        Ok(UserBuilder::default()
+            .id(id)
+            .username(username.into())
+            .email(format!("{username}@protomail.com"))
+            .password(cypher.encode("123")?)
+            .language("en".parse()?)
+            .build()?)
+    }
+
+    #[test]
+    fn test_user_hash() -> Result<(), Box<dyn std::error::Error>> {
+        let cypher = PasswordCypher::default();
+        let users: HashSet<User> = [
+            build_user(&cypher, 1, "someuser100")?,
+            build_user(&cypher, 1, "someuser200")?,
+        ]
+        .into();
+        assert_eq!(users.len(), 1);
+        let users: HashSet<User> = [
+            build_user(&cypher, 1, "someuser100")?,
+            build_user(&cypher, 2, "someuser100")?,
+        ]
+        .into();
+        assert_eq!(users.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_display() -> Result<(), Box<dyn std::error::Error>> {
+        let cypher = PasswordCypher::default();
+        let user = build_user(&cypher, 1, "someuser100")?;
+        let display = user.to_string();
+        assert_eq!(
+            display,
+            "User id 1, username someuser100, email s******@protomail.com, language English"
+        );
+        assert!(!display.contains(user.email()));
+        assert!(!display.contains(&user.password.to_phc_string()));
+        assert!(!display.contains(&Base64Unpadded::encode_string(&user.password.hash)));
+        assert_eq!(user.email(), "someuser100@protomail.com");
+        assert_eq!(mask_email("not an email"), "******");
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_debug() -> Result<(), Box<dyn std::error::Error>> {
+        let cypher = PasswordCypher::default();
+        let user = build_user(&cypher, 1, "someuser100")?;
+        let debug = format!("{user:?}");
+        assert_eq!(
+            debug,
+            "User { id: 1, username: \"someuser100\", email: \"s******@protomail.com\", \
+             language: English, .. }"
+        );
+        assert!(!debug.contains(user.email()));
+        Ok(())
+    }
+
+    // Stores the raw password in the hash for testing, never do this for real!
+    struct StubHasher;
+
+    impl PasswordHasher for StubHasher {
+        fn encode(&self, raw_text: &SecretString) -> Result<Password> {
+            let raw_text = raw_text.expose_secret();
+            let mut hash = vec![0; 32];
+            hash[..raw_text.len()].copy_from_slice(raw_text.as_bytes());
+            Ok(Password {
+                salt: vec![0; 16],
+                hash,
+                algorithm: Algorithm::default(),
+                version: Version::default(),
+                params: Params::default(),
+            })
+        }
+
+        fn verify(&self, password: &Password, raw_text: &SecretString) -> Result<bool> {
+            Ok(self.encode(raw_text)?.hash == password.hash)
+        }
+    }
+
+    #[test]
+    fn test_user_try_password_with_hasher_trait_object() -> Result<(), Box<dyn std::error::Error>> {
+        let hasher: &dyn PasswordHasher = &StubHasher;
+        let user = UserBuilder::default()
+            .id(1)
+            .username("someuser100".into())
+            .email("someuser100@protomail.com".into())
+            .password(hasher.encode(&"123".into())?)
+            .language("en".parse()?)
+            .build()?;
+        assert!(user.try_password_with(hasher, "123")?);
+        assert!(!user.try_password_with(hasher, "1234")?);
+        assert!(!user.try_password_with(&PasswordCypher::default(), "123")?);
+        Ok(())
+    }
+}
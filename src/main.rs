@@ -1,203 +1,103 @@
-use std::{fmt, str::FromStr};
-
-use argon2::Argon2;
-use derive_builder::Builder;
-use derive_getters::Getters;
-
-use derive_new::new;
-use rand::{rngs::OsRng, Rng};
-use strum::{EnumProperty, IntoEnumIterator};
-use strum_macros::{Display, EnumIter, EnumProperty};
-use thiserror::Error;
-
-#[derive(Debug, Display, Clone, PartialEq, Eq, EnumIter, EnumProperty)]
-pub enum Language {
-    #[strum(props(lang = "en"))]
-    English,
-    #[strum(props(lang = "zh"))]
-    Chinese,
-    #[strum(props(lang = "es"))]
-    Spanish,
-    #[strum(props(lang = "fr"))]
-    French,
-    #[strum(props(lang = "de"))]
-    German,
-    #[strum(props(lang = "ja"))]
-    Japanese,
-    #[strum(props(lang = "pt"))]
-    Portuguese,
-    #[strum(props(lang = "ru"))]
-    Russian,
-    #[strum(props(lang = "ar"))]
-    Arabic,
-    #[strum(props(lang = "ko"))]
-    Korean,
-}
-
-#[derive(Debug, Error)]
-pub enum ApplicationError {
-    #[error("Language Not Found")]
-    LanguageNotFound,
-    #[error("Password Hashing Failed: {0}")]
-    PasswordHash(String),
-}
-
-impl FromStr for Language {
-    type Err = ApplicationError;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Self::iter()
-            .find(|l| l.get_str("lang") == Some(s))
-            .ok_or(Self::Err::LanguageNotFound)
-    }
+use std::{
+    io::{self, BufRead, Read, Write},
+    process::ExitCode,
+};
+
+use clap::{Parser, Subcommand};
+use secure_web_applictions_in_rust::{
+    ApplicationError, Language, Password, PasswordCypher, Result, SecretString, UserBuilder,
+};
+
+// Far more than any password, it only stops a runaway pipe from filling memory
+const MAX_LINE_BYTES: u64 = 64 * 1024;
+
+/// Hashes and checks passwords from the command line. Argon2 settings come from
+/// ARGON2_MEMORY_KIB, ARGON2_ITERATIONS and ARGON2_PARALLELISM.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
 }
 
-#[derive(Clone)]
-struct Password {
-    salt: [u8; 16],
-    hash: [u8; 32],
+#[derive(Subcommand)]
+enum Command {
+    /// Reads a password from stdin and prints its PHC string
+    Hash,
+    /// Reads a password from stdin and checks it against a PHC string, exits 1 on mismatch
+    Verify { phc: String },
+    /// Prompts for a new user's details and prints their credential bundle
+    NewUser {
+        #[arg(long)]
+        id: i64,
+    },
 }
 
-#[derive(Clone, Default, new)]
-struct PasswordCypher<'a> {
-    argon: Argon2<'a>,
-}
-
-impl<'a> PasswordCypher<'a> {
-    pub fn encode(&self, raw_text: &str) -> Result<Password, ApplicationError> {
-        let mut salt = [0; 16];
-        let mut hash = [0; 32];
-        OsRng::fill(&mut OsRng, &mut salt);
-        self.argon
-            .hash_password_into(raw_text.as_bytes(), &salt, &mut hash)
-            .map_err(|e| ApplicationError::PasswordHash(e.to_string()))?;
-        Ok(Password { salt, hash })
-    }
-
-    pub fn try_password(
-        &self,
-        password: &Password,
-        raw_text: &str,
-    ) -> Result<bool, ApplicationError> {
-        let argon = Argon2::default();
-        let mut hash = [0; 32];
-        argon
-            .hash_password_into(raw_text.as_bytes(), &password.salt, &mut hash)
-            .map_err(|e| ApplicationError::PasswordHash(e.to_string()))?;
-        Ok(password.hash == hash)
-    }
-}
-
-impl fmt::Debug for Password {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        // Let's keep the password a secret
-        f.debug_struct("Password").finish()
-    }
-}
-
-#[derive(Debug, Clone, Getters, Builder)]
-struct User {
-    id: i64,
-    username: String,
-    email: String,
-    // Lets keep the password a secret
-    #[getter(skip)]
-    password: Password,
-    language: Language,
-}
-
-impl PartialEq for User {
-    fn eq(&self, other: &Self) -> bool {
-        self.id == other.id
-    }
-}
-
-impl User {
-    pub fn try_password_with(
-        &self,
-        cypher: &PasswordCypher,
-        raw_password: &str,
-    ) -> Result<bool, ApplicationError> {
-        cypher.try_password(&self.password, raw_password)
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::from(2)
+        }
     }
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let cypher = PasswordCypher::default();
-    let user = UserBuilder::default()
-        .id(1)
-        .username("someuser100".into())
-        .email("someuser100@protomail.com".into())
-        .password(cypher.encode("123")?)
-        .language("en".parse()?)
-        .build()?;
-    println!(
-        "User id {}, username {}, email {}, language {}",
-        user.id(),
-        user.username(),
-        user.email(),
-        user.language(),
-    );
-    println!(
-        "Does the password 1234 work? {}",
-        if user.try_password_with(&cypher, "1234")? {
-            "yes"
-        } else {
-            "no"
+fn run(cli: Cli) -> Result<ExitCode> {
+    let cypher = PasswordCypher::from_env()?;
+    let mut stdin = io::stdin().lock();
+    match cli.command {
+        Command::Hash => {
+            let password = cypher.encode_reader(&mut stdin)?;
+            println!("{}", password.to_phc_string());
+            Ok(ExitCode::SUCCESS)
         }
-    );
-    println!(
-        "Does the password 123 work? {}",
-        if user.try_password_with(&cypher, "123")? {
-            "yes"
-        } else {
-            "no"
+        Command::Verify { phc } => {
+            let password = Password::from_phc_string(&phc)?;
+            if cypher.try_password(&password, read_line(&mut stdin, "password")?)? {
+                println!("ok");
+                Ok(ExitCode::SUCCESS)
+            } else {
+                println!("mismatch");
+                Ok(ExitCode::FAILURE)
+            }
+        }
+        Command::NewUser { id } => {
+            let username = prompt(&mut stdin, "Username")?;
+            let email = prompt(&mut stdin, "Email")?;
+            let language: Language = prompt(&mut stdin, "Language (e.g. en)")?
+                .expose_secret()
+                .parse()?;
+            eprint!("Password: ");
+            let password = read_line(&mut stdin, "password")?;
+            let user = UserBuilder::default()
+                .id(id)
+                .username(username.expose_secret().into())
+                .email(email.expose_secret().into())
+                .language(language)
+                .raw_password(&cypher, password)?
+                .build_validated()?;
+            println!("{}", user.to_credential_bundle());
+            Ok(ExitCode::SUCCESS)
         }
-    );
-    // Okay let's peak into the password-- for science!
-    // We can do this since everything is in the same module
-    // granting access to private properties
-    // do not do this in production!
-    let p = cypher.encode("123")?;
-    println!("The salt is:\n{:#?}\nThe hash is:\n{:#?}", p.salt, p.hash);
-    Ok(())
-}
-
-#[cfg(test)]
-mod test {
-    use super::*;
-
-    #[test]
-    fn test_password_cypher_encode() -> Result<(), ApplicationError> {
-        let cypher = PasswordCypher::default();
-        let password = cypher.encode("123")?;
-        assert!(!password.salt.iter().all(|b| *b == 0u8));
-        assert!(!password.hash.iter().all(|b| *b == 0u8));
-        assert_ne!(password.hash, "123".as_bytes());
-        Ok(())
     }
+}
 
-    #[test]
-    fn test_password_cypher_try_password() -> Result<(), ApplicationError> {
-        let cypher = PasswordCypher::default();
-        let password = cypher.encode("123")?;
-        assert!(cypher.try_password(&password, "123")?);
-        assert!(!cypher.try_password(&password, "1234")?);
-        Ok(())
-    }
+// Prompts go to stderr so stdout only carries the result
+fn prompt(input: &mut impl BufRead, label: &str) -> Result<SecretString> {
+    eprint!("{label}: ");
+    read_line(input, label)
+}
 
-    #[test]
-    fn test_user_try_password_with() -> Result<(), Box<dyn std::error::Error>> {
-        let cypher = PasswordCypher::default();
-        let user = UserBuilder::default()
-            .id(1)
-            .username("someuser100".into())
-            .email("someuser100@protomail.com".into())
-            .password(cypher.encode("123")?)
-            .language("en".parse()?)
-            .build()?;
-        assert!(user.try_password_with(&cypher, "123")?);
-        assert!(!user.try_password_with(&cypher, "1234")?);
-        Ok(())
-    }
+// The line is kept in a SecretString since it may be a password
+fn read_line(input: &mut impl BufRead, label: &str) -> Result<SecretString> {
+    io::stderr().flush().ok();
+    let mut line = String::new();
+    input
+        .take(MAX_LINE_BYTES)
+        .read_line(&mut line)
+        .map_err(|e| ApplicationError::Encoding(format!("could not read {label}: {e}")))?;
+    let length = line.trim_end_matches(['\r', '\n']).len();
+    line.truncate(length);
+    Ok(SecretString::new(line))
 }
@@ -0,0 +1,394 @@
+use derive_builder::Builder;
+use derive_getters::Getters;
+use subtle::ConstantTimeEq;
+use thiserror::Error;
+
+use crate::{
+    denylist::Denylist, normalize, password_strength, ApplicationError, Language, PasswordStrength,
+    SecretString, DEFAULT_MAX_PASSWORD_LENGTH,
+};
+
+// encode limits passwords in bytes, the policy counts characters. A UTF-8 character is at
+// most 4 bytes, so anything within this many characters also fits encode's limit.
+const DEFAULT_MAX_PASSWORD_CHARS: usize = DEFAULT_MAX_PASSWORD_LENGTH / 4;
+
+/// One rule of a `PasswordPolicy` the password didn't meet
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum PolicyViolation {
+    #[error("must be at least {0} characters")]
+    TooShort(usize),
+    #[error("must be at most {0} characters")]
+    TooLong(usize),
+    #[error("must contain a lowercase letter")]
+    MissingLowercase,
+    #[error("must contain an uppercase letter")]
+    MissingUppercase,
+    #[error("must contain a digit")]
+    MissingDigit,
+    #[error("must contain a symbol")]
+    MissingSymbol,
+    #[error("must be at least {0} strength")]
+    TooWeak(PasswordStrength),
+    #[error("is too common")]
+    Denylisted,
+    #[error("found in {0} known breaches")]
+    Breached(u64),
+    // Fails closed, a password that couldn't be checked isn't accepted
+    #[error("could not be checked against known breaches")]
+    BreachCheckUnavailable,
+}
+
+impl PolicyViolation {
+    /// A full sentence for showing to an end user, walking the language's fallback chain
+    /// until a translation is found, e.g. "Password must be at least 8 characters"
+    pub fn localized_message(&self, lang: Language) -> String {
+        lang.fallback_chain()
+            .iter()
+            .find_map(|lang| self.translation(lang))
+            .unwrap_or_else(|| format!("Password {self}"))
+    }
+
+    fn translation(&self, lang: &Language) -> Option<String> {
+        use Language::{French, German, Spanish};
+        let message = match (lang, self) {
+            (Spanish, Self::TooShort(n)) => {
+                format!("La contraseña debe tener al menos {n} caracteres")
+            }
+            (Spanish, Self::TooLong(n)) => {
+                format!("La contraseña debe tener como máximo {n} caracteres")
+            }
+            (Spanish, Self::MissingLowercase) => "La contraseña debe contener una minúscula".into(),
+            (Spanish, Self::MissingUppercase) => "La contraseña debe contener una mayúscula".into(),
+            (Spanish, Self::MissingDigit) => "La contraseña debe contener un dígito".into(),
+            (Spanish, Self::MissingSymbol) => "La contraseña debe contener un símbolo".into(),
+            (Spanish, Self::TooWeak(_)) => "La contraseña es demasiado débil".into(),
+            (Spanish, Self::Denylisted) => "La contraseña es demasiado común".into(),
+            (Spanish, Self::Breached(n)) => {
+                format!("La contraseña aparece en {n} filtraciones conocidas")
+            }
+            (Spanish, Self::BreachCheckUnavailable) => {
+                "No se pudo comprobar la contraseña en filtraciones conocidas".into()
+            }
+            (French, Self::TooShort(n)) => {
+                format!("Le mot de passe doit contenir au moins {n} caractères")
+            }
+            (French, Self::TooLong(n)) => {
+                format!("Le mot de passe doit contenir au plus {n} caractères")
+            }
+            (French, Self::MissingLowercase) => {
+                "Le mot de passe doit contenir une minuscule".into()
+            }
+            (French, Self::MissingUppercase) => {
+                "Le mot de passe doit contenir une majuscule".into()
+            }
+            (French, Self::MissingDigit) => "Le mot de passe doit contenir un chiffre".into(),
+            (French, Self::MissingSymbol) => "Le mot de passe doit contenir un symbole".into(),
+            (French, Self::TooWeak(_)) => "Le mot de passe est trop faible".into(),
+            (French, Self::Denylisted) => "Le mot de passe est trop courant".into(),
+            (French, Self::Breached(n)) => {
+                format!("Le mot de passe figure dans {n} fuites connues")
+            }
+            (French, Self::BreachCheckUnavailable) => {
+                "Le mot de passe n'a pas pu être vérifié contre les fuites connues".into()
+            }
+            (German, Self::TooShort(n)) => {
+                format!("Das Passwort muss mindestens {n} Zeichen lang sein")
+            }
+            (German, Self::TooLong(n)) => {
+                format!("Das Passwort darf höchstens {n} Zeichen lang sein")
+            }
+            (German, Self::MissingLowercase) => {
+                "Das Passwort muss einen Kleinbuchstaben enthalten".into()
+            }
+            (German, Self::MissingUppercase) => {
+                "Das Passwort muss einen Großbuchstaben enthalten".into()
+            }
+            (German, Self::MissingDigit) => "Das Passwort muss eine Ziffer enthalten".into(),
+            (German, Self::MissingSymbol) => "Das Passwort muss ein Sonderzeichen enthalten".into(),
+            (German, Self::TooWeak(_)) => "Das Passwort ist zu schwach".into(),
+            (German, Self::Denylisted) => "Das Passwort ist zu verbreitet".into(),
+            (German, Self::Breached(n)) => {
+                format!("Das Passwort kommt in {n} bekannten Datenlecks vor")
+            }
+            (German, Self::BreachCheckUnavailable) => {
+                "Das Passwort konnte nicht mit bekannten Datenlecks abgeglichen werden".into()
+            }
+            _ => return None,
+        };
+        Some(message)
+    }
+}
+
+/// Every requirement the password failed, so the user can fix them all at once
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("Password Policy Not Met: {}", .violations.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))]
+pub struct PasswordPolicyError {
+    pub violations: Vec<PolicyViolation>,
+}
+
+/// The rules a new password must follow, run it before `PasswordCypher::encode`
+///
+/// By default only the length is checked (8 to 256 characters), following NIST's advice
+/// against composition rules. Every rule can be switched on or off independently.
+#[derive(Debug, Clone, Getters, Builder)]
+#[builder(default, build_fn(error = "ApplicationError"))]
+pub struct PasswordPolicy {
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+    require_lowercase: bool,
+    require_uppercase: bool,
+    require_digit: bool,
+    require_symbol: bool,
+    min_strength: Option<PasswordStrength>,
+    denylist: Option<Denylist>,
+    // Sends a SHA-1 prefix to HaveIBeenPwned, see `breach::breach_check`
+    #[cfg(feature = "http")]
+    reject_breached: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: Some(8),
+            max_length: Some(DEFAULT_MAX_PASSWORD_CHARS),
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            min_strength: None,
+            denylist: None,
+            #[cfg(feature = "http")]
+            reject_breached: false,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    pub fn validate(
+        &self,
+        raw_password: impl Into<SecretString>,
+    ) -> Result<(), PasswordPolicyError> {
+        let raw_password = raw_password.into();
+        // Characters are counted after the same normalization encode applies
+        let normalized = normalize(raw_password.expose_secret());
+        let mut violations = Vec::new();
+        let length = normalized.chars().count();
+        if let Some(min) = self.min_length.filter(|&min| length < min) {
+            violations.push(PolicyViolation::TooShort(min));
+        }
+        if let Some(max) = self.max_length.filter(|&max| length > max) {
+            violations.push(PolicyViolation::TooLong(max));
+        }
+        if self.require_lowercase && !normalized.chars().any(char::is_lowercase) {
+            violations.push(PolicyViolation::MissingLowercase);
+        }
+        if self.require_uppercase && !normalized.chars().any(char::is_uppercase) {
+            violations.push(PolicyViolation::MissingUppercase);
+        }
+        if self.require_digit && !normalized.chars().any(char::is_numeric) {
+            violations.push(PolicyViolation::MissingDigit);
+        }
+        let is_symbol = |c: char| !c.is_alphanumeric() && !c.is_whitespace();
+        if self.require_symbol && !normalized.chars().any(is_symbol) {
+            violations.push(PolicyViolation::MissingSymbol);
+        }
+        if let Some(min) = self.min_strength {
+            if password_strength(&normalized) < min {
+                violations.push(PolicyViolation::TooWeak(min));
+            }
+        }
+        if let Some(denylist) = &self.denylist {
+            if denylist.contains(&normalized) {
+                violations.push(PolicyViolation::Denylisted);
+            }
+        }
+        #[cfg(feature = "http")]
+        if self.reject_breached {
+            match crate::breach::breach_check(raw_password.clone()) {
+                Ok(0) => {}
+                Ok(count) => violations.push(PolicyViolation::Breached(count)),
+                Err(_) => violations.push(PolicyViolation::BreachCheckUnavailable),
+            }
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(PasswordPolicyError { violations })
+        }
+    }
+
+    /// Checks the password was typed the same twice, e.g. on a registration form.
+    /// Both are normalized the way encode does and compared in constant time.
+    pub fn check_confirmation(password: &str, confirmation: &str) -> Result<(), ApplicationError> {
+        let (password, confirmation) = (normalize(password), normalize(confirmation));
+        if bool::from(password.as_bytes().ct_eq(confirmation.as_bytes())) {
+            Ok(())
+        } else {
+            Err(ApplicationError::PasswordMismatch)
+        }
+    }
+
+    /// Same as `validate`, with each failed rule as a message in `lang` for the user
+    pub fn validate_localized(
+        &self,
+        raw_password: impl Into<SecretString>,
+        lang: Language,
+    ) -> Result<(), Vec<String>> {
+        self.validate(raw_password).map_err(|e| {
+            e.violations
+                .iter()
+                .map(|violation| violation.localized_message(lang.clone()))
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_password_policy_default() {
+        let policy = PasswordPolicy::default();
+        assert!(policy.validate("correct horse").is_ok());
+        assert_eq!(
+            policy.validate("short"),
+            Err(PasswordPolicyError {
+                violations: vec![PolicyViolation::TooShort(8)]
+            })
+        );
+    }
+
+    #[test]
+    fn test_password_policy_multiple_violations() -> crate::Result<()> {
+        let policy = PasswordPolicyBuilder::default()
+            .min_length(Some(12))
+            .require_uppercase(true)
+            .require_digit(true)
+            .require_symbol(true)
+            .min_strength(Some(PasswordStrength::Fair))
+            .build()?;
+        let violations = policy
+            .validate("password")
+            .err()
+            .map(|e| e.violations)
+            .unwrap_or_default();
+        assert_eq!(
+            violations,
+            vec![
+                PolicyViolation::TooShort(12),
+                PolicyViolation::MissingUppercase,
+                PolicyViolation::MissingDigit,
+                PolicyViolation::MissingSymbol,
+                PolicyViolation::TooWeak(PasswordStrength::Fair),
+            ]
+        );
+        assert!(policy.validate("Tr0ub4dor&3-horse").is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_policy_max_length_fits_encode() -> crate::Result<()> {
+        let policy = PasswordPolicy::default();
+        let cypher = crate::PasswordCypherBuilder::default()
+            .memory_cost(1024)
+            .build()?;
+        // 4 bytes each, the longest a character gets
+        let longest = "😀".repeat(256);
+        assert!(policy.validate(longest.as_str()).is_ok());
+        assert!(cypher.encode(longest.as_str()).is_ok());
+        let too_long = "😀".repeat(257);
+        assert_eq!(
+            policy.validate(too_long.as_str()),
+            Err(PasswordPolicyError {
+                violations: vec![PolicyViolation::TooLong(256)]
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_policy_rules_toggle_off() -> crate::Result<()> {
+        let policy = PasswordPolicyBuilder::default()
+            .min_length(None)
+            .max_length(None)
+            .build()?;
+        assert!(policy.validate("a").is_ok());
+        assert!(policy.validate("a".repeat(2048)).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_policy_denylist() -> crate::Result<()> {
+        let policy = PasswordPolicyBuilder::default()
+            .denylist(Some(Denylist::new("iloveyou\nsunshine1\n")))
+            .build()?;
+        assert_eq!(
+            policy.validate("SunShine1"),
+            Err(PasswordPolicyError {
+                violations: vec![PolicyViolation::Denylisted]
+            })
+        );
+        assert!(policy.validate("correct horse").is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_policy_validate_localized() -> crate::Result<()> {
+        let policy = PasswordPolicy::default();
+        assert_eq!(
+            policy.validate_localized("short", Language::Spanish),
+            Err(vec![
+                "La contraseña debe tener al menos 8 caracteres".to_string()
+            ])
+        );
+        assert_eq!(
+            policy.validate_localized("short", Language::English),
+            Err(vec!["Password must be at least 8 characters".to_string()])
+        );
+        // Untranslated languages fall back to English
+        assert_eq!(
+            policy.validate_localized("short", Language::Korean),
+            Err(vec!["Password must be at least 8 characters".to_string()])
+        );
+        let policy = PasswordPolicyBuilder::default()
+            .require_digit(true)
+            .build()?;
+        assert_eq!(
+            policy.validate_localized("short", Language::Spanish),
+            Err(vec![
+                "La contraseña debe tener al menos 8 caracteres".to_string(),
+                "La contraseña debe contener un dígito".to_string(),
+            ])
+        );
+        assert!(policy
+            .validate_localized("correct horse 1", Language::Spanish)
+            .is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_policy_check_confirmation() {
+        assert!(PasswordPolicy::check_confirmation("correct horse", "correct horse").is_ok());
+        // Composed and decomposed forms encode the same, so they match
+        assert!(PasswordPolicy::check_confirmation("ñandú", "n\u{303}andu\u{301}").is_ok());
+        for confirmation in ["correct horsE", "correct horse ", ""] {
+            assert!(matches!(
+                PasswordPolicy::check_confirmation("correct horse", confirmation),
+                Err(ApplicationError::PasswordMismatch)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_password_policy_error_message() {
+        let error: ApplicationError = PasswordPolicy::default()
+            .validate("short")
+            .unwrap_err()
+            .into();
+        assert_eq!(
+            error.to_string(),
+            "Password Policy Not Met: must be at least 8 characters"
+        );
+    }
+}
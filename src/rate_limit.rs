@@ -0,0 +1,179 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+// Buckets are pruned once there are this many, then again each time their number doubles
+const MIN_PRUNE_LEN: usize = 1024;
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    by_key: HashMap<String, Bucket>,
+    prune_at_len: usize,
+}
+
+impl Default for Buckets {
+    fn default() -> Self {
+        Self {
+            by_key: HashMap::new(),
+            prune_at_len: MIN_PRUNE_LEN,
+        }
+    }
+}
+
+/// A token bucket per key (an IP, a username, ...), put in front of login and password
+/// reset endpoints. Clones share the same buckets so it can be handed to every handler.
+///
+/// Keys come from clients, so buckets that have refilled are dropped as the map grows,
+/// a full bucket behaves the same as a new one.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    burst: u32,
+    refill_every: Duration,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimiter {
+    /// Allows `burst` requests at once, then one more every `refill_every`
+    pub fn new(burst: u32, refill_every: Duration) -> Self {
+        Self {
+            burst,
+            refill_every,
+            buckets: Arc::default(),
+        }
+    }
+
+    /// True when the request may go ahead, using up one token for the key
+    pub fn check_and_consume(&self, key: &str) -> bool {
+        self.check_and_consume_at(key, Instant::now())
+    }
+
+    /// Drops the buckets that have refilled, this also happens as more keys are seen
+    pub fn prune(&self) {
+        self.prune_at(Instant::now());
+    }
+
+    fn prune_at(&self, now: Instant) {
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        self.prune_buckets(&mut buckets, now);
+    }
+
+    fn prune_buckets(&self, buckets: &mut Buckets, now: Instant) {
+        buckets
+            .by_key
+            .retain(|_, bucket| self.refilled(bucket, now) < f64::from(self.burst));
+        buckets.prune_at_len = (buckets.by_key.len() * 2).max(MIN_PRUNE_LEN);
+    }
+
+    // The bucket's tokens as of now, capped at the burst
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let burst = f64::from(self.burst);
+        let elapsed = now.saturating_duration_since(bucket.refilled_at);
+        let refilled = if self.refill_every.is_zero() {
+            burst
+        } else {
+            elapsed.as_secs_f64() / self.refill_every.as_secs_f64()
+        };
+        (bucket.tokens + refilled).min(burst)
+    }
+
+    fn check_and_consume_at(&self, key: &str, now: Instant) -> bool {
+        // A panic elsewhere doesn't make the counts wrong, keep limiting
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        if buckets.by_key.len() >= buckets.prune_at_len {
+            self.prune_buckets(&mut buckets, now);
+        }
+        let bucket = buckets.by_key.entry(key.to_owned()).or_insert(Bucket {
+            tokens: f64::from(self.burst),
+            refilled_at: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.refilled_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter_exhausts() {
+        let limiter = RateLimiter::new(3, Duration::from_secs(60));
+        let now = Instant::now();
+        assert!(limiter.check_and_consume_at("10.0.0.1", now));
+        assert!(limiter.check_and_consume_at("10.0.0.1", now));
+        assert!(limiter.check_and_consume_at("10.0.0.1", now));
+        assert!(!limiter.check_and_consume_at("10.0.0.1", now));
+        // Other keys have their own bucket
+        assert!(limiter.check_and_consume_at("10.0.0.2", now));
+    }
+
+    #[test]
+    fn test_rate_limiter_refills() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(10));
+        let now = Instant::now();
+        assert!(limiter.check_and_consume_at("someuser100", now));
+        assert!(limiter.check_and_consume_at("someuser100", now));
+        assert!(!limiter.check_and_consume_at("someuser100", now + Duration::from_secs(5)));
+        assert!(limiter.check_and_consume_at("someuser100", now + Duration::from_secs(10)));
+        assert!(!limiter.check_and_consume_at("someuser100", now + Duration::from_secs(10)));
+        // Never refills past the burst
+        let later = now + Duration::from_secs(1000);
+        assert!(limiter.check_and_consume_at("someuser100", later));
+        assert!(limiter.check_and_consume_at("someuser100", later));
+        assert!(!limiter.check_and_consume_at("someuser100", later));
+    }
+
+    #[test]
+    fn test_rate_limiter_prune() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(10));
+        let now = Instant::now();
+        assert!(limiter.check_and_consume_at("10.0.0.1", now));
+        assert!(limiter.check_and_consume_at("10.0.0.2", now + Duration::from_secs(5)));
+        limiter.prune_at(now + Duration::from_secs(10));
+        let buckets = limiter
+            .buckets
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        assert_eq!(buckets.by_key.keys().collect::<Vec<_>>(), ["10.0.0.2"]);
+    }
+
+    #[test]
+    fn test_rate_limiter_prunes_as_keys_grow() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(1));
+        let now = Instant::now();
+        for i in 0..MIN_PRUNE_LEN {
+            assert!(limiter.check_and_consume_at(&i.to_string(), now));
+        }
+        // Every earlier bucket has refilled by now, so they're dropped
+        let later = now + Duration::from_secs(1);
+        assert!(limiter.check_and_consume_at("spray", later));
+        let buckets = limiter
+            .buckets
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        assert_eq!(buckets.by_key.len(), 1);
+        assert_eq!(buckets.prune_at_len, MIN_PRUNE_LEN);
+    }
+
+    #[test]
+    fn test_rate_limiter_clones_share_buckets() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+        let shared = limiter.clone();
+        assert!(limiter.check_and_consume("someuser100"));
+        assert!(!shared.check_and_consume("someuser100"));
+    }
+}
@@ -0,0 +1,96 @@
+use rand::{rngs::OsRng, Rng};
+
+use crate::{Password, PasswordCypher, Result, SecretString};
+
+// No 0/o, 1/l/i so codes read back off paper without mistakes
+const CODE_ALPHABET: &[u8] = b"23456789abcdefghjkmnpqrstuvwxyz";
+const CODE_GROUP_LENGTH: usize = 5;
+
+/// Single use 2FA backup codes, only their Argon2 hashes are kept
+#[derive(Debug, Clone)]
+pub struct RecoveryCodes {
+    // A used code becomes None so it can never match again
+    hashes: Vec<Option<Password>>,
+}
+
+impl RecoveryCodes {
+    /// Returns `count` codes to show the user once, and the hashes to store
+    pub fn generate(cypher: &PasswordCypher, count: usize) -> Result<(Vec<SecretString>, Self)> {
+        let codes: Vec<SecretString> = (0..count).map(|_| random_code()).collect();
+        let hashes = codes
+            .iter()
+            .map(|code| cypher.encode(canonical(code.expose_secret())).map(Some))
+            .collect::<Result<_>>()?;
+        Ok((codes, Self { hashes }))
+    }
+
+    /// True when the code matches an unused one, which is then used up
+    pub fn verify_and_consume(&mut self, cypher: &PasswordCypher, code: &str) -> Result<bool> {
+        let code = canonical(code);
+        for slot in &mut self.hashes {
+            let Some(hash) = slot else {
+                continue;
+            };
+            if cypher.try_password(hash, code.clone())? {
+                *slot = None;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.hashes.iter().flatten().count()
+    }
+}
+
+fn random_code() -> SecretString {
+    let mut code = String::with_capacity(CODE_GROUP_LENGTH * 2 + 1);
+    for i in 0..CODE_GROUP_LENGTH * 2 {
+        if i == CODE_GROUP_LENGTH {
+            code.push('-');
+        }
+        code.push(CODE_ALPHABET[OsRng.gen_range(0..CODE_ALPHABET.len())].into());
+    }
+    SecretString::new(code)
+}
+
+// Users retype codes, so ignore case, spaces and the dash
+fn canonical(code: &str) -> SecretString {
+    SecretString::new(
+        code.chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_lowercase())
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::PasswordCypherBuilder;
+
+    #[test]
+    fn test_recovery_codes_single_use() -> Result<()> {
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let (codes, mut recovery) = RecoveryCodes::generate(&cypher, 3)?;
+        assert_eq!(codes.len(), 3);
+        assert_eq!(recovery.remaining(), 3);
+        assert!(codes.iter().all(|c| c.expose_secret().len() == 11));
+        let code = codes[1].expose_secret();
+        assert!(recovery.verify_and_consume(&cypher, code)?);
+        assert!(!recovery.verify_and_consume(&cypher, code)?);
+        assert_eq!(recovery.remaining(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_recovery_codes_ignore_formatting() -> Result<()> {
+        let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+        let (codes, mut recovery) = RecoveryCodes::generate(&cypher, 1)?;
+        let retyped = codes[0].expose_secret().replace('-', " ").to_uppercase();
+        assert!(recovery.verify_and_consume(&cypher, &retyped)?);
+        assert!(!recovery.verify_and_consume(&cypher, "nope")?);
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::BTreeMap;
+
+use derive_builder::Builder;
+use derive_getters::Getters;
+use strum_macros::{Display, IntoStaticStr};
+
+use crate::{
+    policy::PasswordPolicy, validate_email, ApplicationError, EmailPolicy, Language, SecretString,
+    UsernameRules,
+};
+
+/// What a sign-up form submits
+#[derive(Debug, Clone)]
+pub struct RegistrationInput {
+    pub username: String,
+    pub email: String,
+    pub password: SecretString,
+    // The password typed a second time
+    pub confirmation: SecretString,
+}
+
+/// The form inputs errors are reported against, displayed in snake_case to match
+/// typical input names
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum RegistrationField {
+    Username,
+    Email,
+    Password,
+    Confirmation,
+}
+
+/// Localized messages for each input that failed, ready to render next to it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegistrationErrors {
+    fields: BTreeMap<RegistrationField, Vec<String>>,
+}
+
+impl RegistrationErrors {
+    /// The field's messages, empty when it passed
+    pub fn get(&self, field: RegistrationField) -> &[String] {
+        self.fields
+            .get(&field)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (RegistrationField, &[String])> {
+        self.fields
+            .iter()
+            .map(|(field, messages)| (*field, messages.as_slice()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    fn add(&mut self, field: RegistrationField, messages: Vec<String>) {
+        if !messages.is_empty() {
+            self.fields.entry(field).or_default().extend(messages);
+        }
+    }
+}
+
+/// Checks a whole sign-up form at once so every input's problems can be shown together
+#[derive(Debug, Clone, Default, Getters, Builder)]
+#[builder(default, build_fn(error = "ApplicationError"))]
+pub struct RegistrationValidator {
+    username_rules: UsernameRules,
+    email_policy: Option<EmailPolicy>,
+    password_policy: PasswordPolicy,
+}
+
+impl RegistrationValidator {
+    pub fn validate(
+        &self,
+        input: &RegistrationInput,
+        lang: Language,
+    ) -> Result<(), RegistrationErrors> {
+        let localized = |result: crate::Result<()>| -> Vec<String> {
+            result
+                .err()
+                .map(|e| field_message(e, lang.clone()))
+                .into_iter()
+                .collect()
+        };
+        let mut errors = RegistrationErrors::default();
+        errors.add(
+            RegistrationField::Username,
+            localized(self.username_rules.validate(&input.username)),
+        );
+        let email = input.email.to_lowercase();
+        errors.add(
+            RegistrationField::Email,
+            localized(
+                validate_email(&email).and_then(|()| match &self.email_policy {
+                    Some(policy) => policy.validate(&email),
+                    None => Ok(()),
+                }),
+            ),
+        );
+        errors.add(
+            RegistrationField::Password,
+            self.password_policy
+                .validate_localized(input.password.clone(), lang.clone())
+                .err()
+                .unwrap_or_default(),
+        );
+        errors.add(
+            RegistrationField::Confirmation,
+            localized(PasswordPolicy::check_confirmation(
+                input.password.expose_secret(),
+                input.confirmation.expose_secret(),
+            )),
+        );
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+// A sentence to show next to the input, without the error's "Invalid ...:" prefix
+fn field_message(error: ApplicationError, lang: Language) -> String {
+    match error {
+        ApplicationError::InvalidUsername(violation) => violation.localized_message(lang),
+        ApplicationError::InvalidEmail(violation) => violation.localized_message(lang),
+        ApplicationError::PasswordMismatch => lang
+            .fallback_chain()
+            .iter()
+            .find_map(|lang| error.translation(lang))
+            .unwrap_or_else(|| "Passwords do not match".into()),
+        error => error.localized_message(lang),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::EmailPolicyBuilder;
+
+    fn input(username: &str, email: &str, password: &str, confirmation: &str) -> RegistrationInput {
+        RegistrationInput {
+            username: username.into(),
+            email: email.into(),
+            password: password.into(),
+            confirmation: confirmation.into(),
+        }
+    }
+
+    #[test]
+    fn test_registration_validator_accepts() {
+        let validator = RegistrationValidator::default();
+        let input = input(
+            "someuser100",
+            "SomeUser100@protomail.com",
+            "correct horse",
+            "correct horse",
+        );
+        assert_eq!(validator.validate(&input, Language::English), Ok(()));
+    }
+
+    #[test]
+    fn test_registration_validator_english() -> crate::Result<()> {
+        let validator = RegistrationValidatorBuilder::default()
+            .email_policy(Some(
+                EmailPolicyBuilder::default()
+                    .blocked_domains(Some(HashSet::from(["mailinator.com".into()])))
+                    .build()?,
+            ))
+            .build()?;
+        let errors = validator
+            .validate(
+                &input("a", "someone@mailinator.com", "short", "shorter"),
+                Language::English,
+            )
+            .err()
+            .unwrap_or_default();
+        assert_eq!(
+            errors.get(RegistrationField::Username),
+            ["Username must be 3 to 32 characters long"]
+        );
+        assert_eq!(
+            errors.get(RegistrationField::Email),
+            ["Email domain 'mailinator.com' is blocked"]
+        );
+        assert_eq!(
+            errors.get(RegistrationField::Password),
+            ["Password must be at least 8 characters"]
+        );
+        assert_eq!(
+            errors.get(RegistrationField::Confirmation),
+            ["Passwords do not match"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_registration_validator_spanish() {
+        let validator = RegistrationValidator::default();
+        let errors = validator
+            .validate(
+                &input("a", "not-an-email", "short", "shorter"),
+                Language::Spanish,
+            )
+            .err()
+            .unwrap_or_default();
+        let fields: Vec<_> = errors.iter().map(|(field, _)| field.to_string()).collect();
+        assert_eq!(fields, ["username", "email", "password", "confirmation"]);
+        assert_eq!(
+            errors.get(RegistrationField::Username),
+            ["El nombre de usuario debe tener entre 3 y 32 caracteres"]
+        );
+        assert_eq!(
+            errors.get(RegistrationField::Email),
+            ["Al correo electrónico le falta '@'"]
+        );
+        assert_eq!(
+            errors.get(RegistrationField::Password),
+            ["La contraseña debe tener al menos 8 caracteres"]
+        );
+        assert_eq!(
+            errors.get(RegistrationField::Confirmation),
+            ["Las contraseñas no coinciden"]
+        );
+    }
+
+    #[test]
+    fn test_registration_validator_german() {
+        let validator = RegistrationValidator::default();
+        let errors = validator
+            .validate(
+                &input("some!user", "someone@", "correct horse", "correct horse"),
+                Language::German,
+            )
+            .err()
+            .unwrap_or_default();
+        assert_eq!(
+            errors.get(RegistrationField::Username),
+            ["Der Benutzername darf '!' nicht enthalten"]
+        );
+        assert_eq!(
+            errors.get(RegistrationField::Email),
+            ["Der E-Mail fehlt die Domain"]
+        );
+        assert!(errors.get(RegistrationField::Password).is_empty());
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+
+use crate::{ApplicationError, Result, UniqueField, User, UsernameKey};
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteUserRepository;
+
+/// Where users are stored, swap the in-memory implementation for a database in production
+pub trait UserRepository {
+    fn find_by_id(&self, id: i64) -> Result<Option<User>>;
+    /// Matches by `UsernameKey`, the same way uniqueness is enforced
+    fn find_by_username(&self, username: &str) -> Result<Option<User>>;
+    /// Fails when the id, username or email is already taken
+    fn insert(&mut self, user: User) -> Result<()>;
+    /// Replaces the stored user with the same id
+    fn update(&mut self, user: User) -> Result<()>;
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryUserRepository {
+    users: HashMap<i64, User>,
+}
+
+impl InMemoryUserRepository {
+    // Usernames and emails must stay unique across every other user, usernames compared
+    // by UsernameKey so "UserA" and "usera" are taken together
+    fn check_unique(&self, user: &User) -> Result<()> {
+        let username = UsernameKey::from(user);
+        for other in self.users.values().filter(|u| u.id != user.id) {
+            if UsernameKey::from(other) == username {
+                return Err(ApplicationError::DuplicateUser(UniqueField::Username));
+            }
+            if other.email == user.email {
+                return Err(ApplicationError::DuplicateUser(UniqueField::Email));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl UserRepository for InMemoryUserRepository {
+    fn find_by_id(&self, id: i64) -> Result<Option<User>> {
+        Ok(self.users.get(&id).cloned())
+    }
+
+    fn find_by_username(&self, username: &str) -> Result<Option<User>> {
+        let username = UsernameKey::new(username);
+        Ok(self
+            .users
+            .values()
+            .find(|u| UsernameKey::from(*u) == username)
+            .cloned())
+    }
+
+    fn insert(&mut self, user: User) -> Result<()> {
+        if self.users.contains_key(&user.id) {
+            return Err(ApplicationError::DuplicateUser(UniqueField::Id));
+        }
+        self.check_unique(&user)?;
+        self.users.insert(user.id, user);
+        Ok(())
+    }
+
+    fn update(&mut self, user: User) -> Result<()> {
+        if !self.users.contains_key(&user.id) {
+            return Err(ApplicationError::UserNotFound);
+        }
+        self.check_unique(&user)?;
+        self.users.insert(user.id, user);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{PasswordCypher, UserBuilder};
+
+    fn build_user(id: i64, username: &str, email: &str) -> Result<User> {
+        UserBuilder::default()
+            .id(id)
+            .username(username.into())
+            .email(email.into())
+            .password(PasswordCypher::default().encode("123")?)
+            .language("en".parse()?)
+            .build()
+    }
+
+    #[test]
+    fn test_in_memory_user_repository_insert() -> Result<()> {
+        let mut repo = InMemoryUserRepository::default();
+        repo.insert(build_user(1, "someuser100", "someuser100@protomail.com")?)?;
+        let user = repo.find_by_id(1)?.ok_or(ApplicationError::UserNotFound)?;
+        assert_eq!(user.username(), "someuser100");
+        let user = repo
+            .find_by_username("someuser100")?
+            .ok_or(ApplicationError::UserNotFound)?;
+        assert_eq!(user.id(), &1);
+        assert!(user.try_password_with(&PasswordCypher::default(), "123")?);
+        // Found the same way uniqueness is checked
+        let user = repo
+            .find_by_username("SomeUser100")?
+            .ok_or(ApplicationError::UserNotFound)?;
+        assert_eq!(user.username(), "someuser100");
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_memory_user_repository_rejects_duplicates() -> Result<()> {
+        let mut repo = InMemoryUserRepository::default();
+        repo.insert(build_user(1, "someuser100", "someuser100@protomail.com")?)?;
+        for (user, field) in [
+            (
+                build_user(1, "someuser200", "someuser200@protomail.com")?,
+                UniqueField::Id,
+            ),
+            (
+                build_user(2, "someuser100", "someuser200@protomail.com")?,
+                UniqueField::Username,
+            ),
+            (
+                build_user(2, "SomeUser100", "someuser200@protomail.com")?,
+                UniqueField::Username,
+            ),
+            (
+                build_user(2, "someuser200", "someuser100@protomail.com")?,
+                UniqueField::Email,
+            ),
+        ] {
+            assert!(matches!(
+                repo.insert(user),
+                Err(ApplicationError::DuplicateUser(f)) if f == field
+            ));
+        }
+        repo.insert(build_user(2, "someuser200", "someuser200@protomail.com")?)?;
+        assert!(matches!(
+            repo.update(build_user(2, "someuser100", "someuser200@protomail.com")?),
+            Err(ApplicationError::DuplicateUser(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_memory_user_repository_update() -> Result<()> {
+        let mut repo = InMemoryUserRepository::default();
+        assert!(matches!(
+            repo.update(build_user(1, "someuser100", "someuser100@protomail.com")?),
+            Err(ApplicationError::UserNotFound)
+        ));
+        repo.insert(build_user(1, "someuser100", "someuser100@protomail.com")?)?;
+        repo.update(build_user(1, "someuser300", "someuser100@protomail.com")?)?;
+        assert!(repo.find_by_username("someuser100")?.is_none());
+        assert!(repo.find_by_username("someuser300")?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_memory_user_repository_lookup_miss() -> Result<()> {
+        let repo = InMemoryUserRepository::default();
+        assert!(repo.find_by_id(1)?.is_none());
+        assert!(repo.find_by_username("someuser100")?.is_none());
+        Ok(())
+    }
+}
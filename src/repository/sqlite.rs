@@ -0,0 +1,469 @@
+use std::time::SystemTime;
+
+use rusqlite::{params, Connection, ErrorCode, OptionalExtension, Row};
+use strum::EnumProperty;
+
+use super::UserRepository;
+use crate::{
+    detect_scheme, from_unix_seconds, history::PasswordHistory, to_unix_seconds, totp::TotpSecret,
+    ApplicationError, HashScheme, Password, Result, User, UsernameKey,
+};
+
+/// Stores users in SQLite with the password kept as its PHC string
+pub struct SqliteUserRepository {
+    conn: Connection,
+}
+
+impl From<rusqlite::Error> for ApplicationError {
+    fn from(e: rusqlite::Error) -> Self {
+        // Unique constraint messages name the column, e.g. "UNIQUE constraint failed: users.email"
+        if let rusqlite::Error::SqliteFailure(failure, Some(msg)) = &e {
+            if failure.code == ErrorCode::ConstraintViolation {
+                if let Some((_, column)) = msg.rsplit_once("users.") {
+                    let column = column.strip_suffix("_key").unwrap_or(column);
+                    if let Ok(field) = column.parse() {
+                        return Self::DuplicateUser(field);
+                    }
+                }
+            }
+        }
+        Self::Database(e.to_string())
+    }
+}
+
+impl SqliteUserRepository {
+    pub fn new(conn: Connection) -> Self {
+        Self { conn }
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Ok(Self::new(Connection::open_in_memory()?))
+    }
+
+    /// Creates the users table if it doesn't exist yet, and adds columns that tables
+    /// made by older versions are missing
+    pub fn migrate(&self) -> Result<()> {
+        self.conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS users (
+                id INTEGER PRIMARY KEY,
+                username TEXT NOT NULL UNIQUE,
+                -- The UsernameKey, so usernames differing only in case collide
+                username_key TEXT NOT NULL,
+                email TEXT NOT NULL UNIQUE,
+                password TEXT NOT NULL,
+                language TEXT NOT NULL,
+                verified INTEGER NOT NULL DEFAULT 0,
+                status TEXT NOT NULL DEFAULT 'pending_verification',
+                -- Space separated role names
+                roles TEXT NOT NULL DEFAULT 'user',
+                -- Unix seconds
+                created_at INTEGER NOT NULL,
+                last_login INTEGER,
+                totp TEXT,
+                -- Space separated PHC strings, oldest first
+                password_history TEXT NOT NULL DEFAULT ''
+            );",
+        )?;
+        let has_username_key: bool = self.conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('users') WHERE name = 'username_key'",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_username_key {
+            self.conn
+                .execute("ALTER TABLE users ADD COLUMN username_key TEXT", [])?;
+            let usernames = self
+                .conn
+                .prepare("SELECT id, username FROM users")?
+                .query_map([], |row| {
+                    Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            for (id, username) in usernames {
+                self.conn.execute(
+                    "UPDATE users SET username_key = ?2 WHERE id = ?1",
+                    params![id, UsernameKey::new(&username).as_str()],
+                )?;
+            }
+        }
+        self.conn.execute(
+            "CREATE UNIQUE INDEX IF NOT EXISTS users_username_key ON users (username_key)",
+            [],
+        )?;
+        Ok(())
+    }
+
+    fn find_where(&self, condition: &str, value: &dyn rusqlite::ToSql) -> Result<Option<User>> {
+        let row = self
+            .conn
+            .query_row(
+                &format!(
+                    "SELECT id, username, email, password, language, verified, status, roles, created_at, last_login, totp, \
+                     password_history FROM users \
+                     WHERE {condition}"
+                ),
+                [value],
+                UserRow::from_row,
+            )
+            .optional()?;
+        row.map(UserRow::into_user).transpose()
+    }
+}
+
+// Raw column values, turned into a User once outside of rusqlite's row callback
+struct UserRow {
+    id: i64,
+    username: String,
+    email: String,
+    password: String,
+    language: String,
+    verified: bool,
+    status: String,
+    roles: String,
+    created_at: i64,
+    last_login: Option<i64>,
+    totp: Option<String>,
+    password_history: String,
+}
+
+impl UserRow {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            username: row.get(1)?,
+            email: row.get(2)?,
+            password: row.get(3)?,
+            language: row.get(4)?,
+            verified: row.get(5)?,
+            status: row.get(6)?,
+            roles: row.get(7)?,
+            created_at: row.get(8)?,
+            last_login: row.get(9)?,
+            totp: row.get(10)?,
+            password_history: row.get(11)?,
+        })
+    }
+
+    // Built directly rather than through UserBuilder, the row was validated when it was
+    // stored and rules that have changed since mustn't make it unreadable
+    fn into_user(self) -> Result<User> {
+        let mut password_history = PasswordHistory::default();
+        for phc in self.password_history.split_whitespace() {
+            password_history.push(Password::from_phc_string(phc)?);
+        }
+        let roles = self
+            .roles
+            .split_whitespace()
+            .map(|role| {
+                role.parse()
+                    .map_err(|_| ApplicationError::Database(format!("bad role '{role}'")))
+            })
+            .collect::<Result<_>>()?;
+        // Legacy hashes share the password column until the user's first login
+        let (password, legacy_password) = match detect_scheme(&self.password) {
+            Some(HashScheme::Bcrypt | HashScheme::Scrypt) => {
+                (Password::redacted(), Some(self.password))
+            }
+            _ => (Password::from_phc_string(&self.password)?, None),
+        };
+        Ok(User {
+            id: self.id,
+            username: self.username,
+            email: self.email,
+            password,
+            language: self.language.parse()?,
+            verified: self.verified,
+            status: self
+                .status
+                .parse()
+                .map_err(|_| ApplicationError::Database(format!("bad status '{}'", self.status)))?,
+            roles,
+            created_at: from_sql_time(self.created_at),
+            last_login: self.last_login.map(from_sql_time),
+            totp: self
+                .totp
+                .as_deref()
+                .map(TotpSecret::from_base32)
+                .transpose()?,
+            password_history,
+            legacy_password,
+        })
+    }
+}
+
+fn stored_password(user: &User) -> String {
+    match &user.legacy_password {
+        Some(stored) => stored.clone(),
+        None => user.password.to_phc_string(),
+    }
+}
+
+fn lang_code(user: &User) -> &'static str {
+    user.language.get_str("lang").unwrap_or_default()
+}
+
+fn totp_base32(user: &User) -> Option<String> {
+    user.totp
+        .as_ref()
+        .map(|totp| totp.to_base32().expose_secret().to_owned())
+}
+
+// SQLite integers are signed
+fn to_sql_time(time: SystemTime) -> i64 {
+    i64::try_from(to_unix_seconds(time)).unwrap_or(i64::MAX)
+}
+
+fn from_sql_time(seconds: i64) -> SystemTime {
+    from_unix_seconds(u64::try_from(seconds).unwrap_or_default())
+}
+
+fn role_names(user: &User) -> String {
+    user.roles
+        .iter()
+        .map(|&role| <&str>::from(role))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn history_phc(user: &User) -> String {
+    user.password_history
+        .iter()
+        .map(Password::to_phc_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl UserRepository for SqliteUserRepository {
+    fn find_by_id(&self, id: i64) -> Result<Option<User>> {
+        self.find_where("id = ?1", &id)
+    }
+
+    fn find_by_username(&self, username: &str) -> Result<Option<User>> {
+        self.find_where("username_key = ?1", &UsernameKey::new(username).as_str())
+    }
+
+    fn insert(&mut self, user: User) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO users (id, username, email, password, language, verified, status,
+             roles, created_at, last_login, totp, password_history, username_key)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            params![
+                user.id,
+                user.username,
+                user.email,
+                stored_password(&user),
+                lang_code(&user),
+                user.verified,
+                <&str>::from(user.status),
+                role_names(&user),
+                to_sql_time(user.created_at),
+                user.last_login.map(to_sql_time),
+                totp_base32(&user),
+                history_phc(&user),
+                UsernameKey::from(&user).as_str(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn update(&mut self, user: User) -> Result<()> {
+        let updated = self.conn.execute(
+            "UPDATE users SET username = ?2, email = ?3, password = ?4, language = ?5,
+             verified = ?6, status = ?7, roles = ?8, created_at = ?9,
+             last_login = ?10, totp = ?11, password_history = ?12, username_key = ?13
+             WHERE id = ?1",
+            params![
+                user.id,
+                user.username,
+                user.email,
+                stored_password(&user),
+                lang_code(&user),
+                user.verified,
+                <&str>::from(user.status),
+                role_names(&user),
+                to_sql_time(user.created_at),
+                user.last_login.map(to_sql_time),
+                totp_base32(&user),
+                history_phc(&user),
+                UsernameKey::from(&user).as_str(),
+            ],
+        )?;
+        if updated == 0 {
+            return Err(ApplicationError::UserNotFound);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        AccountStatus, Language, PasswordCypher, Role, UniqueField, UserBuilder,
+        UsernameRulesBuilder,
+    };
+
+    fn build_user(cypher: &PasswordCypher, id: i64, username: &str) -> Result<User> {
+        build_user_with_email(cypher, id, username, &format!("{username}@protomail.com"))
+    }
+
+    fn build_user_with_email(
+        cypher: &PasswordCypher,
+        id: i64,
+        username: &str,
+        email: &str,
+    ) -> Result<User> {
+        UserBuilder::default()
+            .id(id)
+            .username(username.into())
+            .email(email.into())
+            .password(cypher.encode("123")?)
+            .language("ja".parse()?)
+            .build()
+    }
+
+    #[test]
+    fn test_sqlite_user_repository() -> Result<()> {
+        let cypher = PasswordCypher::default();
+        let mut repo = SqliteUserRepository::open_in_memory()?;
+        repo.migrate()?;
+        // Migrating again is harmless
+        repo.migrate()?;
+        repo.insert(build_user(&cypher, 1, "someuser100")?)?;
+        let user = repo
+            .find_by_username("someuser100")?
+            .ok_or(ApplicationError::UserNotFound)?;
+        assert_eq!(user.id(), &1);
+        assert_eq!(user.email(), "someuser100@protomail.com");
+        assert_eq!(user.language(), &Language::Japanese);
+        assert!(user.try_password_with(&cypher, "123")?);
+        assert!(!user.try_password_with(&cypher, "1234")?);
+        assert!(repo.find_by_id(1)?.is_some());
+        assert!(repo.find_by_id(2)?.is_none());
+        assert!(repo.find_by_username("someuser200")?.is_none());
+        assert_eq!(repo.find_by_username("SomeUser100")?.map(|u| u.id), Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_user_repository_reads_back_custom_rules() -> Result<()> {
+        let cypher = PasswordCypher::default();
+        let mut repo = SqliteUserRepository::open_in_memory()?;
+        repo.migrate()?;
+        // Too short and with a symbol the default rules refuse
+        let user = UserBuilder::default()
+            .id(1)
+            .username_rules(
+                UsernameRulesBuilder::default()
+                    .min_length(2)
+                    .max_length(32)
+                    .allowed_symbols(vec!['+'])
+                    .build()?,
+            )
+            .username("a+".into())
+            .email("someuser100@protomail.com".into())
+            .password(cypher.encode("123")?)
+            .language("en".parse()?)
+            .build()?;
+        repo.insert(user)?;
+        let user = repo
+            .find_by_username("a+")?
+            .ok_or(ApplicationError::UserNotFound)?;
+        assert_eq!(user.id(), &1);
+        assert!(repo.find_by_id(1)?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_user_repository_legacy_password() -> Result<()> {
+        let mut repo = SqliteUserRepository::open_in_memory()?;
+        repo.migrate()?;
+        let stored = crate::BcryptCypher::new(4).encode("123")?;
+        let user = UserBuilder::default()
+            .id(1)
+            .username("someuser100".into())
+            .email("someuser100@protomail.com".into())
+            .legacy_password(stored.clone())?
+            .language("en".parse()?)
+            .build()?;
+        repo.insert(user)?;
+        let user = repo.find_by_id(1)?.ok_or(ApplicationError::UserNotFound)?;
+        assert_eq!(user.legacy_password.as_deref(), Some(stored.as_str()));
+        assert!(user.password.is_redacted());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_user_repository_rejects_duplicates() -> Result<()> {
+        let cypher = PasswordCypher::default();
+        let mut repo = SqliteUserRepository::open_in_memory()?;
+        repo.migrate()?;
+        repo.insert(build_user(&cypher, 1, "someuser100")?)?;
+        assert!(matches!(
+            repo.insert(build_user(&cypher, 1, "someuser200")?),
+            Err(ApplicationError::DuplicateUser(f)) if f == UniqueField::Id
+        ));
+        assert!(matches!(
+            repo.insert(build_user_with_email(&cypher, 2, "someuser100", "other@protomail.com")?),
+            Err(ApplicationError::DuplicateUser(f)) if f == UniqueField::Username
+        ));
+        assert!(matches!(
+            repo.insert(build_user_with_email(&cypher, 2, "SomeUser100", "other@protomail.com")?),
+            Err(ApplicationError::DuplicateUser(f)) if f == UniqueField::Username
+        ));
+        assert!(matches!(
+            repo.insert(build_user_with_email(&cypher, 2, "someuser200", "someuser100@protomail.com")?),
+            Err(ApplicationError::DuplicateUser(f)) if f == UniqueField::Email
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_user_repository_migrates_username_key() -> Result<()> {
+        let cypher = PasswordCypher::default();
+        let mut repo = SqliteUserRepository::open_in_memory()?;
+        repo.migrate()?;
+        repo.insert(build_user(&cypher, 1, "SomeUser100")?)?;
+        // Recreate a table from before the username_key column existed
+        repo.conn.execute_batch(
+            "DROP INDEX users_username_key;
+             ALTER TABLE users DROP COLUMN username_key;",
+        )?;
+        repo.migrate()?;
+        assert!(matches!(
+            repo.insert(build_user_with_email(&cypher, 2, "someuser100", "other@protomail.com")?),
+            Err(ApplicationError::DuplicateUser(f)) if f == UniqueField::Username
+        ));
+        assert_eq!(repo.find_by_username("SomeUser100")?.map(|u| u.id), Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_user_repository_update() -> Result<()> {
+        let cypher = PasswordCypher::default();
+        let mut repo = SqliteUserRepository::open_in_memory()?;
+        repo.migrate()?;
+        let mut user = build_user(&cypher, 1, "someuser100")?;
+        assert!(matches!(
+            repo.update(user.clone()),
+            Err(ApplicationError::UserNotFound)
+        ));
+        repo.insert(user.clone())?;
+        user.change_password(&cypher, "123", "456")?;
+        user.verified = true;
+        user.set_status(AccountStatus::Active);
+        user.add_role(Role::Admin);
+        user.record_login();
+        user.set_totp(Some(TotpSecret::generate()));
+        repo.update(user)?;
+        let user = repo.find_by_id(1)?.ok_or(ApplicationError::UserNotFound)?;
+        assert!(user.try_password_with(&cypher, "456")?);
+        assert!(*user.verified());
+        assert!(user.is_active());
+        assert!(user.has_role(Role::Admin) && user.has_role(Role::User));
+        assert!(user.last_login().is_some());
+        assert!(user.has_totp());
+        assert!(user.password_history.was_recently_used(&cypher, "123")?);
+        Ok(())
+    }
+}
@@ -0,0 +1,339 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+use derive_getters::Getters;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{
+    encoding::{decode_urlsafe_nopad, encode_urlsafe_nopad},
+    token::{generate_token, hash_token},
+    Result, SecretString,
+};
+
+const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// What a session token hashes to, the only form of it the server keeps
+pub type SessionHash = [u8; 32];
+
+/// The cookie value handed to the browser, 32 random bytes as URL-safe base64
+#[derive(Debug, Clone)]
+pub struct SessionToken(SecretString);
+
+impl SessionToken {
+    pub fn expose_secret(&self) -> &str {
+        self.0.expose_secret()
+    }
+}
+
+/// Who a session belongs to and how long it lives
+#[derive(Debug, Clone, Getters)]
+pub struct Session {
+    user_id: i64,
+    // None when the ttl is too long to represent, the session then never expires
+    expires_at: Option<SystemTime>,
+    last_seen: SystemTime,
+}
+
+/// Where sessions are kept, keyed by the hash of their token
+pub trait SessionStore {
+    fn get(&self, hash: &SessionHash) -> Result<Option<Session>>;
+    /// Adds or replaces the session
+    fn put(&mut self, hash: SessionHash, session: Session) -> Result<()>;
+    fn remove(&mut self, hash: &SessionHash) -> Result<()>;
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InMemorySessionStore {
+    sessions: HashMap<SessionHash, Session>,
+}
+
+impl SessionStore for InMemorySessionStore {
+    fn get(&self, hash: &SessionHash) -> Result<Option<Session>> {
+        Ok(self.sessions.get(hash).cloned())
+    }
+
+    fn put(&mut self, hash: SessionHash, session: Session) -> Result<()> {
+        self.sessions.insert(hash, session);
+        Ok(())
+    }
+
+    fn remove(&mut self, hash: &SessionHash) -> Result<()> {
+        self.sessions.remove(hash);
+        Ok(())
+    }
+}
+
+/// Issues and checks sessions, by default they last a day with a 30 minute idle timeout
+#[derive(Debug, Clone)]
+pub struct SessionManager<S: SessionStore> {
+    store: S,
+    ttl: Duration,
+    idle_timeout: Option<Duration>,
+}
+
+impl<S: SessionStore + Default> Default for SessionManager<S> {
+    fn default() -> Self {
+        Self::new(
+            S::default(),
+            DEFAULT_SESSION_TTL,
+            Some(DEFAULT_IDLE_TIMEOUT),
+        )
+    }
+}
+
+impl<S: SessionStore> SessionManager<S> {
+    pub fn new(store: S, ttl: Duration, idle_timeout: Option<Duration>) -> Self {
+        Self {
+            store,
+            ttl,
+            idle_timeout,
+        }
+    }
+
+    /// Starts a session, the returned token goes in the cookie and is never stored
+    pub fn issue(&mut self, user_id: i64) -> Result<SessionToken> {
+        let (token, hash) = generate_token();
+        let now = SystemTime::now();
+        self.store.put(
+            hash,
+            Session {
+                user_id,
+                expires_at: now.checked_add(self.ttl),
+                last_seen: now,
+            },
+        )?;
+        Ok(SessionToken(token))
+    }
+
+    /// The session's user, or None when the token is unknown, expired or idle too long.
+    /// Valid sessions have their idle timer reset.
+    pub fn validate(&mut self, token: &str) -> Result<Option<i64>> {
+        let hash = hash_token(token);
+        let Some(mut session) = self.store.get(&hash)? else {
+            return Ok(None);
+        };
+        let now = SystemTime::now();
+        // Timeouts too long to represent never run out
+        let idle = self.idle_timeout.is_some_and(|timeout| {
+            session
+                .last_seen
+                .checked_add(timeout)
+                .is_some_and(|until| until <= now)
+        });
+        let expired = session.expires_at.is_some_and(|at| now >= at);
+        if expired || idle {
+            self.store.remove(&hash)?;
+            return Ok(None);
+        }
+        session.last_seen = now;
+        let user_id = session.user_id;
+        self.store.put(hash, session)?;
+        Ok(Some(user_id))
+    }
+
+    /// Ends the session, e.g. on logout
+    pub fn revoke(&mut self, token: &str) -> Result<()> {
+        self.store.remove(&hash_token(token))
+    }
+}
+
+/// Anti-CSRF tokens derived from the session, nothing extra needs storing
+///
+/// The token is an HMAC of the session id under a server secret, so it can't be forged
+/// without the secret and a token from one session doesn't work in another.
+#[derive(Clone)]
+pub struct CsrfToken<'a> {
+    secret: &'a [u8],
+}
+
+impl<'a> CsrfToken<'a> {
+    pub fn new(secret: &'a [u8]) -> Self {
+        Self { secret }
+    }
+
+    fn mac(&self, session_id: &str) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(self.secret).expect("HMAC takes any key length");
+        mac.update(session_id.as_bytes());
+        mac
+    }
+
+    /// The token to embed in the session's forms
+    pub fn generate(&self, session_id: &str) -> String {
+        encode_urlsafe_nopad(&self.mac(session_id).finalize().into_bytes())
+    }
+
+    /// Compares in constant time, any malformed token is simply rejected
+    pub fn verify(&self, session_id: &str, token: &str) -> bool {
+        let Ok(tag) = decode_urlsafe_nopad(token) else {
+            return false;
+        };
+        self.mac(session_id).verify_slice(&tag).is_ok()
+    }
+}
+
+/// A payload that travels in a cookie alongside an HMAC of it, e.g. a user id for
+/// stateless sessions. The payload isn't encrypted, only protected from tampering.
+///
+/// Signed values look like `<payload>.<tag>`, both as URL-safe base64.
+#[derive(Clone)]
+pub struct SignedValue<'a> {
+    secret: &'a [u8],
+}
+
+impl<'a> SignedValue<'a> {
+    pub fn new(secret: &'a [u8]) -> Self {
+        Self { secret }
+    }
+
+    fn mac(&self, payload: &[u8]) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(self.secret).expect("HMAC takes any key length");
+        mac.update(payload);
+        mac
+    }
+
+    pub fn sign(&self, payload: &str) -> String {
+        let tag = self.mac(payload.as_bytes()).finalize().into_bytes();
+        format!(
+            "{}.{}",
+            encode_urlsafe_nopad(payload.as_bytes()),
+            encode_urlsafe_nopad(&tag)
+        )
+    }
+
+    /// The payload when the tag matches, compared in constant time. Anything malformed
+    /// or tampered with is None.
+    pub fn verify(&self, signed: &str) -> Option<String> {
+        let (payload, tag) = signed.split_once('.')?;
+        let payload = decode_urlsafe_nopad(payload).ok()?;
+        let tag = decode_urlsafe_nopad(tag).ok()?;
+        self.mac(&payload).verify_slice(&tag).ok()?;
+        String::from_utf8(payload).ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_session_issue_validate() -> Result<()> {
+        let mut sessions = SessionManager::<InMemorySessionStore>::default();
+        let token = sessions.issue(1)?;
+        assert_eq!(token.expose_secret().len(), 43);
+        assert_eq!(sessions.validate(token.expose_secret())?, Some(1));
+        assert_eq!(sessions.validate("not a session")?, None);
+        // Only the hash is stored
+        assert!(sessions
+            .store
+            .get(&hash_token(token.expose_secret()))?
+            .is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_expiry() -> Result<()> {
+        let mut sessions =
+            SessionManager::new(InMemorySessionStore::default(), Duration::ZERO, None);
+        let token = sessions.issue(1)?;
+        assert_eq!(sessions.validate(token.expose_secret())?, None);
+        assert!(sessions.store.sessions.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_never_expires() -> Result<()> {
+        let mut sessions = SessionManager::new(
+            InMemorySessionStore::default(),
+            Duration::MAX,
+            Some(Duration::MAX),
+        );
+        let token = sessions.issue(1)?;
+        assert_eq!(sessions.validate(token.expose_secret())?, Some(1));
+        let session = sessions.store.get(&hash_token(token.expose_secret()))?;
+        assert_eq!(session.map(|s| s.expires_at), Some(None));
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_idle_timeout() -> Result<()> {
+        let mut sessions = SessionManager::new(
+            InMemorySessionStore::default(),
+            DEFAULT_SESSION_TTL,
+            Some(Duration::ZERO),
+        );
+        let token = sessions.issue(1)?;
+        assert_eq!(sessions.validate(token.expose_secret())?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_csrf_token() {
+        let csrf = CsrfToken::new(b"server secret");
+        let token = csrf.generate("session-1");
+        assert!(csrf.verify("session-1", &token));
+        assert_eq!(token, csrf.generate("session-1"));
+        assert!(!CsrfToken::new(b"other secret").verify("session-1", &token));
+    }
+
+    #[test]
+    fn test_csrf_token_tampered() {
+        let csrf = CsrfToken::new(b"server secret");
+        let mut token = csrf.generate("session-1").into_bytes();
+        token[0] = if token[0] == b'A' { b'B' } else { b'A' };
+        let token = String::from_utf8(token).unwrap_or_default();
+        assert!(!csrf.verify("session-1", &token));
+        assert!(!csrf.verify("session-1", "not base64!"));
+        assert!(!csrf.verify("session-1", ""));
+    }
+
+    #[test]
+    fn test_csrf_token_cross_session() {
+        let csrf = CsrfToken::new(b"server secret");
+        let token = csrf.generate("session-1");
+        assert!(!csrf.verify("session-2", &token));
+    }
+
+    #[test]
+    fn test_signed_value() {
+        let signer = SignedValue::new(b"server secret");
+        let signed = signer.sign("user:42");
+        assert_eq!(signer.verify(&signed).as_deref(), Some("user:42"));
+        assert_eq!(SignedValue::new(b"other secret").verify(&signed), None);
+    }
+
+    #[test]
+    fn test_signed_value_tampered() {
+        let signer = SignedValue::new(b"server secret");
+        let signed = signer.sign("user:42");
+        let (_, tag) = signed.split_once('.').unwrap_or_default();
+        let forged = format!("{}.{tag}", encode_urlsafe_nopad(b"user:1"));
+        assert_eq!(signer.verify(&forged), None);
+        // Flip the first character of the tag
+        let mut flipped = signed.clone().into_bytes();
+        let at = signed.find('.').unwrap_or_default() + 1;
+        flipped[at] = if flipped[at] == b'A' { b'B' } else { b'A' };
+        assert_eq!(
+            signer.verify(&String::from_utf8(flipped).unwrap_or_default()),
+            None
+        );
+        assert_eq!(signer.verify("no separator"), None);
+        assert_eq!(signer.verify("."), None);
+    }
+
+    #[test]
+    fn test_session_revoke() -> Result<()> {
+        let mut sessions = SessionManager::<InMemorySessionStore>::default();
+        let token = sessions.issue(1)?;
+        let other = sessions.issue(2)?;
+        sessions.revoke(token.expose_secret())?;
+        assert_eq!(sessions.validate(token.expose_secret())?, None);
+        assert_eq!(sessions.validate(other.expose_secret())?, Some(2));
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+use std::{
+    fmt,
+    time::{Duration, SystemTime},
+};
+
+use derive_getters::Getters;
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::{
+    encoding::encode_urlsafe_nopad, mask_email, AccountStatus, ApplicationError, Result,
+    SecretString, User,
+};
+
+const TOKEN_BYTES: usize = 32;
+
+// Tokens are 256 random bits, so a fast hash is enough to stop a leaked table being replayed
+pub(crate) fn hash_token(token: &str) -> [u8; 32] {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+// Returns the url-safe token to send to the user alongside the hash to store
+pub(crate) fn generate_token() -> (SecretString, [u8; 32]) {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    let token = SecretString::new(encode_urlsafe_nopad(&bytes));
+    let hash = hash_token(token.expose_secret());
+    (token, hash)
+}
+
+// The server side half of a token: its hash, when it expires, and whether it was used.
+// A ttl too long to represent has no expiry.
+#[derive(Debug, Clone)]
+struct HashedToken {
+    hash: [u8; 32],
+    expires_at: Option<SystemTime>,
+    consumed: bool,
+}
+
+impl HashedToken {
+    fn issue(ttl: Duration) -> (SecretString, Self) {
+        let (token, hash) = generate_token();
+        let record = Self {
+            hash,
+            expires_at: SystemTime::now().checked_add(ttl),
+            consumed: false,
+        };
+        (token, record)
+    }
+
+    fn verify(&self, token: &str) -> Result<()> {
+        if self.consumed || !bool::from(hash_token(token).ct_eq(&self.hash)) {
+            return Err(ApplicationError::InvalidToken);
+        }
+        if self.expires_at.is_some_and(|at| SystemTime::now() >= at) {
+            return Err(ApplicationError::ExpiredToken);
+        }
+        Ok(())
+    }
+}
+
+/// A single use password reset, only the hash of the emailed token is kept
+#[derive(Debug, Clone, Getters)]
+pub struct PasswordResetToken {
+    user_id: i64,
+    #[getter(skip)]
+    token: HashedToken,
+}
+
+impl PasswordResetToken {
+    /// Returns the token to email to the user and the record to store server side
+    pub fn issue(user_id: i64, ttl: Duration) -> (SecretString, Self) {
+        let (token, record) = HashedToken::issue(ttl);
+        (
+            token,
+            Self {
+                user_id,
+                token: record,
+            },
+        )
+    }
+
+    /// None when the ttl was too long to represent, the token never expires
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.token.expires_at
+    }
+
+    pub fn consumed(&self) -> bool {
+        self.token.consumed
+    }
+
+    /// Checks the token matches and is still usable, without using it up
+    pub fn verify(&self, token: &str) -> Result<()> {
+        self.token.verify(token)
+    }
+
+    /// Verifies the token and marks it used, returning the user whose password may be reset
+    pub fn consume(&mut self, token: &str) -> Result<i64> {
+        self.verify(token)?;
+        self.token.consumed = true;
+        Ok(self.user_id)
+    }
+}
+
+/// Confirms a user owns their email, the token is tied to the address it was sent to
+#[derive(Clone, Getters)]
+pub struct EmailVerificationToken {
+    user_id: i64,
+    email: String,
+    #[getter(skip)]
+    token: HashedToken,
+}
+
+// Same masking as User, the target address is still PII
+impl fmt::Debug for EmailVerificationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EmailVerificationToken")
+            .field("user_id", &self.user_id)
+            .field("email", &mask_email(&self.email))
+            .finish_non_exhaustive()
+    }
+}
+
+impl EmailVerificationToken {
+    /// Returns the token to send to the user's current email and the record to store
+    pub fn issue(user: &User, ttl: Duration) -> (SecretString, Self) {
+        let (token, record) = HashedToken::issue(ttl);
+        (
+            token,
+            Self {
+                user_id: user.id,
+                email: user.email.clone(),
+                token: record,
+            },
+        )
+    }
+
+    /// None when the ttl was too long to represent, the token never expires
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.token.expires_at
+    }
+
+    pub fn consumed(&self) -> bool {
+        self.token.consumed
+    }
+
+    /// Checks the token is usable for this user and the email it was sent to is still theirs
+    pub fn verify(&self, user: &User, token: &str) -> Result<()> {
+        self.token.verify(token)?;
+        if user.id != self.user_id || user.email != self.email {
+            return Err(ApplicationError::InvalidToken);
+        }
+        Ok(())
+    }
+
+    /// Verifies the token, marks it used and flags the user's email as verified, activating
+    /// the account if it was waiting on this
+    pub fn consume(&mut self, user: &mut User, token: &str) -> Result<()> {
+        self.verify(user, token)?;
+        self.token.consumed = true;
+        user.verified = true;
+        if user.status == AccountStatus::PendingVerification {
+            user.status = AccountStatus::Active;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{PasswordCypher, UserBuilder};
+
+    fn build_user() -> Result<User> {
+        UserBuilder::default()
+            .id(1)
+            .username("someuser100".into())
+            .email("someuser100@protomail.com".into())
+            .password(PasswordCypher::default().encode("123")?)
+            .language("en".parse()?)
+            .build()
+    }
+
+    #[test]
+    fn test_reset_token() -> Result<()> {
+        let (token, mut record) = PasswordResetToken::issue(1, Duration::from_secs(60));
+        assert_ne!(record.token.hash, [0u8; 32]);
+        record.verify(token.expose_secret())?;
+        assert_eq!(record.consume(token.expose_secret())?, 1);
+        assert!(record.consumed());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_token_wrong_token() {
+        let (_, record) = PasswordResetToken::issue(1, Duration::from_secs(60));
+        let (other, _) = PasswordResetToken::issue(1, Duration::from_secs(60));
+        assert!(matches!(
+            record.verify(other.expose_secret()),
+            Err(ApplicationError::InvalidToken)
+        ));
+    }
+
+    #[test]
+    fn test_reset_token_expired() {
+        let (token, mut record) = PasswordResetToken::issue(1, Duration::ZERO);
+        assert!(matches!(
+            record.consume(token.expose_secret()),
+            Err(ApplicationError::ExpiredToken)
+        ));
+    }
+
+    #[test]
+    fn test_reset_token_never_expires() -> Result<()> {
+        let (token, mut record) = PasswordResetToken::issue(1, Duration::MAX);
+        assert_eq!(record.expires_at(), None);
+        assert_eq!(record.consume(token.expose_secret())?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_token_reuse() -> Result<()> {
+        let (token, mut record) = PasswordResetToken::issue(1, Duration::from_secs(60));
+        record.consume(token.expose_secret())?;
+        assert!(matches!(
+            record.consume(token.expose_secret()),
+            Err(ApplicationError::InvalidToken)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_email_verification() -> Result<()> {
+        let mut user = build_user()?;
+        assert!(!*user.verified());
+        let (token, mut record) = EmailVerificationToken::issue(&user, Duration::from_secs(60));
+        assert_eq!(record.email(), "someuser100@protomail.com");
+        record.consume(&mut user, token.expose_secret())?;
+        assert!(*user.verified());
+        assert!(user.is_active());
+        assert!(record.consumed());
+        Ok(())
+    }
+
+    #[test]
+    fn test_email_verification_email_changed() -> Result<()> {
+        let mut user = build_user()?;
+        let (token, mut record) = EmailVerificationToken::issue(&user, Duration::from_secs(60));
+        user.email = "someuser200@protomail.com".into();
+        assert!(matches!(
+            record.consume(&mut user, token.expose_secret()),
+            Err(ApplicationError::InvalidToken)
+        ));
+        assert!(!*user.verified());
+        Ok(())
+    }
+}
@@ -0,0 +1,222 @@
+use std::{fmt, time::SystemTime};
+
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use sha1::Sha1;
+use subtle::ConstantTimeEq;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::{to_unix_seconds, ApplicationError, Result, SecretString};
+
+const SECRET_BYTES: usize = 20;
+const TIME_STEP: u64 = 30;
+const DIGITS: u32 = 6;
+/// Steps either side of now that are still accepted, absorbs clock skew between devices
+pub const DEFAULT_WINDOW: u64 = 1;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// A shared TOTP (RFC 6238) secret, SHA-1 with 6 digit codes every 30 seconds since
+/// that's what authenticator apps expect
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct TotpSecret {
+    bytes: Vec<u8>,
+}
+
+impl fmt::Debug for TotpSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TotpSecret([REDACTED])")
+    }
+}
+
+impl TotpSecret {
+    pub fn generate() -> Self {
+        let mut bytes = vec![0u8; SECRET_BYTES];
+        OsRng.fill_bytes(&mut bytes);
+        Self { bytes }
+    }
+
+    /// Parses an unpadded RFC 4648 base32 secret, spaces and case are ignored
+    pub fn from_base32(encoded: &str) -> Result<Self> {
+        let mut bytes = Vec::with_capacity(encoded.len() * 5 / 8);
+        let (mut buffer, mut bits) = (0u32, 0u32);
+        for c in encoded.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+            let c = c.to_ascii_uppercase();
+            let Some(value) = BASE32_ALPHABET.iter().position(|&a| char::from(a) == c) else {
+                return Err(ApplicationError::InvalidTotpSecret(format!(
+                    "'{c}' is not base32"
+                )));
+            };
+            buffer = (buffer << 5) | value as u32;
+            bits += 5;
+            if bits >= 8 {
+                bits -= 8;
+                bytes.push((buffer >> bits) as u8);
+            }
+        }
+        buffer.zeroize();
+        if bytes.is_empty() {
+            return Err(ApplicationError::InvalidTotpSecret("empty".into()));
+        }
+        Ok(Self { bytes })
+    }
+
+    /// The unpadded base32 form users type in when they can't scan the QR code
+    pub fn to_base32(&self) -> SecretString {
+        let mut encoded = String::with_capacity(self.bytes.len().div_ceil(5) * 8);
+        let (mut buffer, mut bits) = (0u32, 0u32);
+        for &byte in &self.bytes {
+            buffer = (buffer << 8) | u32::from(byte);
+            bits += 8;
+            while bits >= 5 {
+                bits -= 5;
+                encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize].into());
+            }
+        }
+        if bits > 0 {
+            encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize].into());
+        }
+        buffer.zeroize();
+        SecretString::new(encoded)
+    }
+
+    /// The `otpauth://` URI to render as a QR code for authenticator apps
+    pub fn provisioning_uri(&self, issuer: &str, account: &str) -> SecretString {
+        let issuer = percent_encode(issuer);
+        SecretString::new(format!(
+            "otpauth://totp/{issuer}:{}?secret={}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={TIME_STEP}",
+            percent_encode(account),
+            self.to_base32().expose_secret(),
+        ))
+    }
+
+    /// The code for the time step containing `unix_time`
+    pub fn code_at(&self, unix_time: u64) -> u32 {
+        self.code_for_step(unix_time / TIME_STEP)
+    }
+
+    /// Checks a code against the current time with the default window
+    pub fn verify(&self, code: &str) -> bool {
+        self.verify_at(code, to_unix_seconds(SystemTime::now()), DEFAULT_WINDOW)
+    }
+
+    /// Checks a code against every step within `window` steps of `unix_time`
+    pub fn verify_at(&self, code: &str, unix_time: u64, window: u64) -> bool {
+        let step = unix_time / TIME_STEP;
+        let mut matched = subtle::Choice::from(0);
+        // Check every step rather than stopping early so the timing doesn't leak which matched
+        for candidate in step.saturating_sub(window)..=step.saturating_add(window) {
+            let expected = format!(
+                "{:0width$}",
+                self.code_for_step(candidate),
+                width = DIGITS as usize
+            );
+            matched |= expected.as_bytes().ct_eq(code.as_bytes());
+        }
+        matched.into()
+    }
+
+    // HOTP (RFC 4226) dynamic truncation of the HMAC over the step counter
+    fn code_for_step(&self, step: u64) -> u32 {
+        let mut mac = Hmac::<Sha1>::new_from_slice(&self.bytes).expect("HMAC takes any key length");
+        mac.update(&step.to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+        let offset = usize::from(digest[digest.len() - 1] & 0x0f);
+        let binary = u32::from_be_bytes([
+            digest[offset],
+            digest[offset + 1],
+            digest[offset + 2],
+            digest[offset + 3],
+        ]) & 0x7fff_ffff;
+        binary % 10u32.pow(DIGITS)
+    }
+}
+
+// Otpauth labels are shown to the user, keep them readable but URI safe
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(b).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for TotpSecret {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.to_base32().expose_secret())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for TotpSecret {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let encoded = zeroize::Zeroizing::new(String::deserialize(deserializer)?);
+        TotpSecret::from_base32(&encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // The RFC 6238 SHA-1 test secret, "12345678901234567890"
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn test_totp_known_code() -> Result<()> {
+        let secret = TotpSecret::from_base32(RFC_SECRET)?;
+        assert_eq!(secret.bytes, b"12345678901234567890");
+        assert_eq!(secret.code_at(59), 287082);
+        assert_eq!(secret.code_at(1111111109), 81804);
+        assert!(secret.verify_at("287082", 59, 0));
+        assert!(secret.verify_at("081804", 1111111109, 0));
+        assert!(!secret.verify_at("81804", 1111111109, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_totp_window() -> Result<()> {
+        let secret = TotpSecret::from_base32(RFC_SECRET)?;
+        // 287082 is the code for the step before
+        assert!(secret.verify_at("287082", 59 + TIME_STEP, DEFAULT_WINDOW));
+        assert!(!secret.verify_at("287082", 59 + TIME_STEP, 0));
+        assert!(!secret.verify_at("287082", 59 + 2 * TIME_STEP, DEFAULT_WINDOW));
+        Ok(())
+    }
+
+    #[test]
+    fn test_totp_base32_round_trip() -> Result<()> {
+        let secret = TotpSecret::generate();
+        let encoded = secret.to_base32();
+        assert_eq!(encoded.expose_secret().len(), 32);
+        assert_eq!(
+            TotpSecret::from_base32(encoded.expose_secret())?.bytes,
+            secret.bytes
+        );
+        assert_eq!(
+            TotpSecret::from_base32(RFC_SECRET)?
+                .to_base32()
+                .expose_secret(),
+            RFC_SECRET
+        );
+        assert!(TotpSecret::from_base32("not base32!").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_totp_provisioning_uri() -> Result<()> {
+        let secret = TotpSecret::from_base32(RFC_SECRET)?;
+        assert_eq!(
+            secret
+                .provisioning_uri("Secure App", "someuser100@protomail.com")
+                .expose_secret(),
+            "otpauth://totp/Secure%20App:someuser100%40protomail.com?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+             &issuer=Secure%20App&algorithm=SHA1&digits=6&period=30"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+// Runs the CLI with `input` piped to stdin, using cheap Argon2 settings to keep tests fast
+fn run(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_secure-web-applictions-in-rust"))
+        .args(args)
+        .env("ARGON2_MEMORY_KIB", "1024")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("the binary runs");
+    // Ignored since the binary may fail and exit before reading all of it
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(input.as_bytes())
+        .ok();
+    child.wait_with_output().expect("the binary exits")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).trim().to_owned()
+}
+
+#[test]
+fn test_hash_then_verify() {
+    let hashed = run(&["hash"], "correct horse\n");
+    assert!(hashed.status.success());
+    let phc = stdout(&hashed);
+    assert!(phc.starts_with("$argon2id$v=19$m=1024,"));
+    let verified = run(&["verify", &phc], "correct horse\n");
+    assert!(verified.status.success());
+    assert_eq!(stdout(&verified), "ok");
+    let rejected = run(&["verify", &phc], "correct horsE\n");
+    assert_eq!(rejected.status.code(), Some(1));
+    assert_eq!(stdout(&rejected), "mismatch");
+}
+
+#[test]
+fn test_verify_rejects_malformed_phc() {
+    let output = run(&["verify", "not a phc string"], "123\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("error: "));
+}
+
+#[test]
+fn test_new_user() {
+    let output = run(
+        &["new-user", "--id", "7"],
+        "someuser100\nSomeUser100@protomail.com\nfr\ncorrect horse\n",
+    );
+    assert!(output.status.success());
+    let bundle = stdout(&output);
+    assert!(bundle.starts_with("{\"version\":1,\"id\":7,\"username\":\"someuser100\""));
+    assert!(bundle.contains("\"email\":\"someuser100@protomail.com\""));
+    assert!(bundle.contains("\"language\":\"fr\""));
+    assert!(!bundle.contains("correct horse"));
+    let invalid = run(&["new-user", "--id", "7"], "a\nnot-an-email\nen\n123\n");
+    assert_eq!(invalid.status.code(), Some(2));
+}
@@ -0,0 +1,73 @@
+use secure_web_applictions_in_rust::{
+    audit::InMemoryAuditSink,
+    auth::authenticate,
+    detect_scheme,
+    repository::{InMemoryUserRepository, UserRepository},
+    AccountStatus, ApplicationError, BcryptCypher, HashScheme, PasswordCypherBuilder, Result,
+    ScryptCypher, User, UserBuilder,
+};
+
+fn legacy_user(stored: String) -> Result<User> {
+    UserBuilder::default()
+        .id(1)
+        .username("someuser100".into())
+        .email("someuser100@protomail.com".into())
+        .legacy_password(stored)?
+        .language("en".parse()?)
+        .status(AccountStatus::Active)
+        .build()
+}
+
+fn migrates_on_login(stored: String) -> Result<()> {
+    let cypher = PasswordCypherBuilder::default().memory_cost(1024).build()?;
+    let audit = InMemoryAuditSink::default();
+    let mut repo = InMemoryUserRepository::default();
+    repo.insert(legacy_user(stored)?)?;
+    assert!(authenticate(&repo, &cypher, &audit, "someuser100", "correct horsE")?.is_none());
+    let authenticated = authenticate(&repo, &cypher, &audit, "someuser100", "correct horse")?
+        .ok_or(ApplicationError::IncorrectPassword)?;
+    let upgraded = authenticated
+        .upgraded_password()
+        .clone()
+        .ok_or(ApplicationError::IncorrectPassword)?;
+    assert_eq!(
+        detect_scheme(&upgraded.to_phc_string()),
+        Some(HashScheme::Argon2)
+    );
+    assert!(cypher.try_password(&upgraded, "correct horse")?);
+    let user = authenticated.into_user();
+    assert!(!user.has_legacy_password());
+    assert!(user.verify_password(&cypher, "correct horse")?);
+    // Once the migrated user is saved there's nothing left to upgrade
+    repo.update(user)?;
+    let again = authenticate(&repo, &cypher, &audit, "someuser100", "correct horse")?
+        .ok_or(ApplicationError::IncorrectPassword)?;
+    assert!(again.upgraded_password().is_none());
+    Ok(())
+}
+
+#[test]
+fn test_bcrypt_migrates_to_argon2_on_login() -> Result<()> {
+    migrates_on_login(BcryptCypher::new(4).encode("correct horse")?)
+}
+
+#[test]
+fn test_scrypt_migrates_to_argon2_on_login() -> Result<()> {
+    migrates_on_login(ScryptCypher::new(4, 8, 1)?.encode("correct horse")?)
+}
+
+#[test]
+fn test_legacy_password_must_be_bcrypt_or_scrypt() -> Result<()> {
+    let phc = PasswordCypherBuilder::default()
+        .memory_cost(1024)
+        .build()?
+        .encode("123")?
+        .to_phc_string();
+    for stored in [phc.as_str(), "5f4dcc3b5aa765d61d8327deb882cf99"] {
+        assert!(matches!(
+            legacy_user(stored.into()),
+            Err(ApplicationError::PasswordHash(_))
+        ));
+    }
+    Ok(())
+}